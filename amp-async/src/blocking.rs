@@ -0,0 +1,379 @@
+//! A synchronous client and server for programs without a runtime.
+
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+use bytes::{Bytes, BytesMut};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio_util::codec::Decoder as _;
+
+use amp_serde::{ErrorResponse, OkResponse, Request};
+
+use crate::codecs::encode;
+use crate::frame::{Frame, RESERVED_KEYS};
+use crate::{
+    AmpVersion, DecodeLimits, Decoder, DuplicateKeys, Error, RawFrame, RemoteError, Version, V1, V2,
+};
+
+const READ_SIZE: usize = 8 * 1024;
+
+pub trait Dispatcher {
+    fn dispatch(&mut self, _command: &str, _frame: RawFrame) -> Result<RawFrame, RemoteError> {
+        Err(RemoteError::new(Some("UNHANDLED"), Option::<&str>::None))
+    }
+
+    fn dispatch_noreply(&mut self, _command: &str, _frame: RawFrame) {}
+}
+
+pub struct NoopDispatcher;
+
+impl Dispatcher for NoopDispatcher {}
+
+/// Connect to an AMP server over TCP.
+pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Connection<TcpStream>, Error> {
+    Ok(Connection::new(TcpStream::connect(addr)?))
+}
+
+/// One AMP connection over a blocking stream.
+///
+/// Requests received while waiting for a reply in `call` are answered
+/// as unhandled; use `call_with` to handle them.
+pub struct Connection<S, V = V1> {
+    stream: S,
+    read_buf: BytesMut,
+    write_buf: BytesMut,
+    decoder: Decoder<V, RawFrame>,
+    version: V,
+    seqno: u64,
+}
+
+impl<S: Read + Write> Connection<S, V1> {
+    pub fn new(stream: S) -> Self {
+        Connection::with_version(stream, V1)
+    }
+}
+
+impl<S: Read + Write, V: AmpVersion> Connection<S, V> {
+    fn with_version(stream: S, version: V) -> Self {
+        Connection {
+            stream,
+            read_buf: BytesMut::new(),
+            write_buf: BytesMut::new(),
+            decoder: Decoder::with_version(version).strict_keys(RESERVED_KEYS),
+            version,
+            seqno: 0,
+        }
+    }
+
+    pub fn version2(self) -> Connection<S, V2> {
+        self.switch_version(V2)
+    }
+
+    /// Pick the protocol version at runtime.
+    pub fn version(self, version: Version) -> Connection<S, Version> {
+        self.switch_version(version)
+    }
+
+    fn switch_version<U: Copy>(self, version: U) -> Connection<S, U> {
+        Connection {
+            stream: self.stream,
+            read_buf: self.read_buf,
+            write_buf: self.write_buf,
+            decoder: self.decoder.into_version(version),
+            version,
+            seqno: self.seqno,
+        }
+    }
+
+    pub fn limits(self, limits: DecodeLimits) -> Self {
+        Connection {
+            decoder: self.decoder.limits(limits),
+            ..self
+        }
+    }
+
+    pub fn duplicate_keys(self, duplicates: DuplicateKeys) -> Self {
+        Connection {
+            decoder: self.decoder.duplicate_keys(duplicates),
+            ..self
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    pub fn call<Q: Serialize, R: DeserializeOwned>(
+        &mut self,
+        command: &str,
+        request: Q,
+    ) -> Result<R, Error> {
+        self.call_with(&mut NoopDispatcher, command, request)
+    }
+
+    /// Like `call`, handing requests that arrive before the reply to
+    /// `dispatcher`.
+    pub fn call_with<D: Dispatcher, Q: Serialize, R: DeserializeOwned>(
+        &mut self,
+        dispatcher: &mut D,
+        command: &str,
+        request: Q,
+    ) -> Result<R, Error> {
+        self.seqno += 1;
+        let tag: Bytes = format!("{:x}", self.seqno).into();
+
+        self.send(Request {
+            tag: Some(tag.clone()),
+            command: command.into(),
+            fields: request,
+        })?;
+
+        loop {
+            let frame = self
+                .read_frame()?
+                .ok_or_else(|| Error::IO(std::io::ErrorKind::UnexpectedEof.into()))?;
+
+            match frame.try_into()? {
+                Frame::Response {
+                    tag: answer,
+                    response,
+                } if answer == tag => {
+                    let raw_frame = response.map_err(Error::Remote)?;
+
+                    return amp_serde::to_bytes_with(self.version, raw_frame)
+                        .and_then(|bytes| amp_serde::from_bytes_with(self.version, bytes))
+                        .map_err(Into::into);
+                }
+                Frame::Response { .. } => return Err(Error::UnmatchedReply),
+                Frame::Request {
+                    command,
+                    tag,
+                    fields,
+                } => self.handle_request(dispatcher, command, tag, fields)?,
+            }
+        }
+    }
+
+    pub fn call_noreply<Q: Serialize>(&mut self, command: &str, request: Q) -> Result<(), Error> {
+        self.send(Request {
+            tag: None,
+            command: command.into(),
+            fields: request,
+        })
+    }
+
+    /// Answer requests until the peer closes the connection.
+    pub fn serve<D: Dispatcher>(&mut self, dispatcher: &mut D) -> Result<(), Error> {
+        while let Some(frame) = self.read_frame()? {
+            match frame.try_into()? {
+                Frame::Request {
+                    command,
+                    tag,
+                    fields,
+                } => self.handle_request(dispatcher, command, tag, fields)?,
+                Frame::Response { .. } => return Err(Error::UnmatchedReply),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_request<D: Dispatcher>(
+        &mut self,
+        dispatcher: &mut D,
+        command: Bytes,
+        tag: Option<Bytes>,
+        fields: RawFrame,
+    ) -> Result<(), Error> {
+        let command = std::str::from_utf8(&command)?;
+
+        match tag {
+            None => {
+                dispatcher.dispatch_noreply(command, fields);
+                Ok(())
+            }
+            Some(tag) => match dispatcher.dispatch(command, fields) {
+                Ok(fields) => self.send(OkResponse { tag, fields }),
+                Err(e) => self.send(ErrorResponse {
+                    tag,
+                    code: e.code,
+                    description: e.description,
+                }),
+            },
+        }
+    }
+
+    fn send<T: Serialize>(&mut self, item: T) -> Result<(), Error> {
+        self.write_buf.clear();
+        encode(self.version, item, &mut self.write_buf)?;
+        self.stream.write_all(&self.write_buf)?;
+        self.stream.flush()?;
+
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> Result<Option<RawFrame>, Error> {
+        let mut chunk = [0; READ_SIZE];
+
+        loop {
+            if let Some(frame) = self.decoder.decode(&mut self.read_buf)? {
+                return Ok(Some(frame));
+            }
+
+            let count = self.stream.read(&mut chunk)?;
+            if count == 0 {
+                return Ok(self.decoder.decode_eof(&mut self.read_buf)?);
+            }
+            self.read_buf.extend_from_slice(&chunk[..count]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Sum {
+        a: i64,
+        b: i64,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Total {
+        total: i64,
+    }
+
+    #[derive(Default)]
+    struct Adder {
+        notes: Vec<String>,
+    }
+
+    impl Dispatcher for Adder {
+        fn dispatch(&mut self, command: &str, frame: RawFrame) -> Result<RawFrame, RemoteError> {
+            let field = |key: &str| -> i64 {
+                std::str::from_utf8(&frame[key.as_bytes()])
+                    .unwrap()
+                    .parse()
+                    .unwrap()
+            };
+
+            match command {
+                "Sum" => {
+                    let mut out = RawFrame::new();
+                    out.insert("total".into(), (field("a") + field("b")).to_string().into());
+                    Ok(out)
+                }
+                _ => Err(RemoteError::new(Some("UNHANDLED"), Some(command))),
+            }
+        }
+
+        fn dispatch_noreply(&mut self, command: &str, _frame: RawFrame) {
+            self.notes.push(command.into());
+        }
+    }
+
+    #[test]
+    fn tcp_calls() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut adder = Adder::default();
+            Connection::new(stream).serve(&mut adder).unwrap();
+            adder.notes
+        });
+
+        let mut client = connect(addr).unwrap();
+        let total: Total = client.call("Sum", Sum { a: 1, b: 2 }).unwrap();
+        assert_eq!(total, Total { total: 3 });
+
+        client.call_noreply("Note", Sum { a: 0, b: 0 }).unwrap();
+
+        match client.call::<_, Total>("Product", Sum { a: 1, b: 2 }) {
+            Err(Error::Remote(e)) => assert_eq!(e.code, "UNHANDLED"),
+            _ => unreachable!(),
+        }
+
+        drop(client);
+        assert_eq!(server.join().unwrap(), vec!["Note"]);
+    }
+
+    #[test]
+    fn requests_during_call() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Before answering, the server asks the client for the total.
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut peer = Connection::new(stream);
+            let asked = peer.read_frame().unwrap().unwrap();
+            let total: RawFrame = peer.call("Sum", Sum { a: 2, b: 3 }).unwrap();
+            peer.send(OkResponse {
+                tag: asked[b"_ask".as_ref()].clone(),
+                fields: total,
+            })
+            .unwrap();
+        });
+
+        let mut client = connect(addr).unwrap();
+        let total: Total = client
+            .call_with(&mut Adder::default(), "Total", Sum { a: 0, b: 0 })
+            .unwrap();
+        assert_eq!(total, Total { total: 5 });
+
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn version2_stream() {
+        let mut request = RawFrame::new();
+        request.insert("blob".into(), vec![b'x'; 100_000].into());
+
+        let mut output = Connection::new(std::io::Cursor::new(Vec::new())).version2();
+        output.call_noreply("Store", &request).unwrap();
+        let written = output.into_inner().into_inner();
+
+        struct Recorder(Vec<RawFrame>);
+
+        impl Dispatcher for Recorder {
+            fn dispatch_noreply(&mut self, _command: &str, frame: RawFrame) {
+                self.0.push(frame);
+            }
+        }
+
+        let mut recorder = Recorder(Vec::new());
+        let stream = std::io::Cursor::new(written);
+        Connection::new(stream)
+            .version2()
+            .serve(&mut recorder)
+            .unwrap();
+        assert_eq!(recorder.0, vec![request]);
+    }
+
+    #[test]
+    fn settings_survive_version() {
+        let limits = DecodeLimits {
+            max_keys: 1,
+            ..Default::default()
+        };
+        let input = b"\x00\x08_command\x00\x04Note\x00\x01a\x00\x011\x00\x00";
+
+        let mut connection = Connection::new(std::io::Cursor::new(input.to_vec()))
+            .limits(limits)
+            .version2();
+        match connection.serve(&mut NoopDispatcher) {
+            Err(Error::Decode(crate::DecodeError::TooManyKeys { limit: 1, .. })) => (),
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+}
@@ -1,39 +1,87 @@
+use std::collections::HashMap;
 use std::iter::Extend;
-use std::marker::PhantomData;
 
 use bytes::{Bytes, BytesMut};
-use tokio_util::codec::{Decoder, LengthDelimitedCodec};
+use serde::Serialize;
+use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
 
-use crate::{AmpVersion, V1, V2};
+use crate::{AmpVersion, DecodeError, Error, RawFrame, Version, V1, V2};
 
 pub(crate) const AMP_KEY_LIMIT: usize = 0xff;
 pub(crate) const AMP_VALUE_LIMIT: usize = 0xffff;
+const AMP_LENGTH_SIZE: usize = 2;
+
+/// Caps protecting the decoder against hostile peers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DecodeLimits {
+    /// Total encoded size of a box, length prefixes included.
+    pub max_box_size: usize,
+    /// Number of keys in a box.
+    pub max_keys: usize,
+    /// Length of a V2 value reassembled from multiple segments.
+    pub max_value_length: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_box_size: 64 * 1024 * 1024,
+            max_keys: 1024,
+            max_value_length: 32 * 1024 * 1024,
+        }
+    }
+}
+
+/// What to do when a key appears more than once in a box.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum DuplicateKeys {
+    Reject,
+    FirstWins,
+    LastWins,
+    /// Hand every pair to the output collection, as received.
+    #[default]
+    KeepAll,
+}
 
 #[derive(Debug)]
 pub struct Dec<V, D = Vec<(Bytes, Bytes)>> {
     state: State,
     key: Bytes,
     value: BytesMut,
-    frame: D,
+    pairs: Vec<(Bytes, Bytes)>,
+    /// Where each key of the box so far first went in `pairs`.
+    seen: HashMap<Bytes, usize>,
+    skip_value: bool,
+    /// The slot in `pairs` the current value replaces.
+    overwrite: Option<usize>,
     decoder: LengthDelimitedCodec,
-    version: PhantomData<V>,
+    limits: DecodeLimits,
+    duplicates: DuplicateKeys,
+    strict_keys: &'static [&'static [u8]],
+    box_size: usize,
+    keys: usize,
+    consumed: u64,
+    /// The box so far, when keys are not tracked.
+    frame: Option<D>,
+    version: V,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, PartialEq)]
 enum State {
+    #[default]
     Key,
     Value,
     ValueCont,
 }
 
-impl Default for State {
+impl<V: Default, D> Default for Dec<V, D> {
     fn default() -> Self {
-        State::Key
+        Dec::with_version(V::default())
     }
 }
 
-impl<V, D: Default> Default for Dec<V, D> {
-    fn default() -> Self {
+impl<V, D> Dec<V, D> {
+    pub fn with_version(version: V) -> Self {
         Dec {
             decoder: LengthDelimitedCodec::builder()
                 .big_endian()
@@ -42,14 +90,46 @@ impl<V, D: Default> Default for Dec<V, D> {
                 .new_codec(),
             key: Default::default(),
             value: Default::default(),
-            frame: Default::default(),
+            pairs: Default::default(),
+            seen: Default::default(),
+            skip_value: false,
+            overwrite: None,
             state: Default::default(),
-            version: PhantomData,
+            limits: Default::default(),
+            duplicates: Default::default(),
+            strict_keys: &[],
+            box_size: 0,
+            keys: 0,
+            consumed: 0,
+            frame: None,
+            version,
+        }
+    }
+
+    /// The same decoder, settings and state included, for another version.
+    pub(crate) fn into_version<U>(self, version: U) -> Dec<U, D> {
+        Dec {
+            state: self.state,
+            key: self.key,
+            value: self.value,
+            pairs: self.pairs,
+            seen: self.seen,
+            skip_value: self.skip_value,
+            overwrite: self.overwrite,
+            decoder: self.decoder,
+            limits: self.limits,
+            duplicates: self.duplicates,
+            strict_keys: self.strict_keys,
+            box_size: self.box_size,
+            keys: self.keys,
+            consumed: self.consumed,
+            frame: self.frame,
+            version,
         }
     }
 }
 
-impl<V, D> Dec<V, D>
+impl<V: Default, D> Dec<V, D>
 where
     D: Default + Extend<(Bytes, Bytes)>,
 {
@@ -57,38 +137,195 @@ where
         Default::default()
     }
 
+    pub fn with_limits(limits: DecodeLimits) -> Self {
+        Dec {
+            limits,
+            ..Dec::new()
+        }
+    }
+}
+
+impl<V: Copy, D> Dec<V, D>
+where
+    D: Default + Extend<(Bytes, Bytes)>,
+{
+    pub fn limits(self, limits: DecodeLimits) -> Self {
+        Dec { limits, ..self }
+    }
+
+    pub fn duplicate_keys(self, duplicates: DuplicateKeys) -> Self {
+        Dec { duplicates, ..self }
+    }
+
+    /// Keys rejected when repeated, whatever the duplicate policy.
+    pub fn strict_keys(self, strict_keys: &'static [&'static [u8]]) -> Self {
+        Dec {
+            strict_keys,
+            ..self
+        }
+    }
+
+    /// Drop the partially decoded box and start over.
+    fn fail(&mut self, error: DecodeError) -> DecodeError {
+        *self = Dec {
+            limits: self.limits,
+            duplicates: self.duplicates,
+            strict_keys: self.strict_keys,
+            consumed: self.consumed,
+            ..Dec::with_version(self.version)
+        };
+        error
+    }
+
+    /// Whether repeated keys need noticing, which costs a copy of every
+    /// key and pair.
+    fn tracks_keys(&self) -> bool {
+        self.duplicates != DuplicateKeys::KeepAll || !self.strict_keys.is_empty()
+    }
+
+    fn start_key(&mut self, key: Bytes, offset: u64) -> Result<(), DecodeError> {
+        if self.tracks_keys() {
+            match self.seen.get(&key).copied() {
+                None => {
+                    self.seen.insert(key.clone(), self.pairs.len());
+                }
+                Some(_) if self.strict_keys.contains(&key.as_ref()) => {
+                    return Err(self.fail(DecodeError::DuplicateKey { key, offset }))
+                }
+                Some(index) => match self.duplicates {
+                    DuplicateKeys::Reject => {
+                        return Err(self.fail(DecodeError::DuplicateKey { key, offset }))
+                    }
+                    DuplicateKeys::FirstWins => self.skip_value = true,
+                    DuplicateKeys::LastWins => self.overwrite = Some(index),
+                    DuplicateKeys::KeepAll => (),
+                },
+            }
+        }
+
+        self.key = key;
+        self.state = State::Value;
+        self.decoder.set_max_frame_length(AMP_VALUE_LIMIT);
+        Ok(())
+    }
+
+    fn finish_value(&mut self, value: Bytes) {
+        let key = std::mem::take(&mut self.key);
+        if std::mem::take(&mut self.skip_value) {
+        } else if let Some(index) = self.overwrite.take() {
+            self.pairs[index].1 = value;
+        } else if self.tracks_keys() {
+            self.pairs.push((key, value));
+        } else {
+            let frame = self.frame.get_or_insert_with(D::default);
+            frame.extend(std::iter::once((key, value)));
+        }
+        self.state = State::Key;
+        self.decoder.set_max_frame_length(AMP_KEY_LIMIT);
+    }
+
+    fn finish_box(&mut self) -> D {
+        self.box_size = 0;
+        self.keys = 0;
+        self.seen.clear();
+
+        let mut frame = self.frame.take().unwrap_or_default();
+        frame.extend(self.pairs.drain(..));
+        frame
+    }
+
+    fn check_limits(&mut self, segment: &BytesMut, offset: u64) -> Result<(), DecodeError> {
+        let limits = self.limits;
+
+        self.box_size += AMP_LENGTH_SIZE + segment.len();
+        if self.state == State::Key && !segment.is_empty() {
+            self.keys += 1;
+        }
+
+        let exceeded = if self.box_size > limits.max_box_size {
+            DecodeError::OversizedBox {
+                limit: limits.max_box_size,
+                offset,
+            }
+        } else if self.keys > limits.max_keys {
+            DecodeError::TooManyKeys {
+                limit: limits.max_keys,
+                offset,
+            }
+        } else if self.state == State::ValueCont
+            && self.value.len() + segment.len() > limits.max_value_length
+        {
+            DecodeError::OversizedValue {
+                limit: limits.max_value_length,
+                offset,
+            }
+        } else {
+            return Ok(());
+        };
+
+        Err(self.fail(exceeded))
+    }
+
+    fn next_segment(&mut self, buf: &mut BytesMut) -> Result<Option<BytesMut>, DecodeError> {
+        let before = buf.len();
+        let res = self.decoder.decode(buf);
+        self.consumed += (before - buf.len()) as u64;
+
+        match res {
+            Err(e) if self.state == State::Key && e.kind() == std::io::ErrorKind::InvalidData => {
+                let offset = self.consumed;
+                Err(self.fail(DecodeError::OversizedKey { offset }))
+            }
+            res => res.map_err(Into::into),
+        }
+    }
+
+    /// Start a V2 value, which continues while segments are full.
+    fn handle_chunked(&mut self, segment: BytesMut) {
+        if segment.len() == AMP_VALUE_LIMIT {
+            self.value = segment;
+            self.state = State::ValueCont;
+        } else {
+            self.finish_value(segment.freeze());
+        }
+    }
+
     fn handle_valuecont(&mut self, segment: BytesMut) {
         self.value.extend_from_slice(&segment);
 
         if segment.len() != AMP_VALUE_LIMIT {
-            let key = std::mem::take(&mut self.key);
             let value = std::mem::take(&mut self.value);
-            self.frame.extend(std::iter::once((key, value.freeze())));
-            self.state = State::Key;
-            self.decoder.set_max_frame_length(AMP_KEY_LIMIT);
+            self.finish_value(value.freeze());
         }
     }
 }
 
 impl AmpVersion for V1 {
-    fn handle_value<D: Extend<(Bytes, Bytes)>>(dec: &mut Dec<Self, D>, segment: BytesMut) {
-        let key = std::mem::take(&mut dec.key);
-        dec.frame.extend(std::iter::once((key, segment.freeze())));
-        dec.state = State::Key;
-        dec.decoder.set_max_frame_length(AMP_KEY_LIMIT);
+    fn handle_value<D>(dec: &mut Dec<Self, D>, segment: BytesMut)
+    where
+        D: Default + Extend<(Bytes, Bytes)>,
+    {
+        dec.finish_value(segment.freeze());
     }
 }
 
 impl AmpVersion for V2 {
-    fn handle_value<D: Extend<(Bytes, Bytes)>>(dec: &mut Dec<Self, D>, segment: BytesMut) {
-        if segment.len() == AMP_VALUE_LIMIT {
-            dec.value = segment;
-            dec.state = State::ValueCont;
-        } else {
-            let key = std::mem::take(&mut dec.key);
-            dec.frame.extend(std::iter::once((key, segment.freeze())));
-            dec.state = State::Key;
-            dec.decoder.set_max_frame_length(AMP_KEY_LIMIT);
+    fn handle_value<D>(dec: &mut Dec<Self, D>, segment: BytesMut)
+    where
+        D: Default + Extend<(Bytes, Bytes)>,
+    {
+        dec.handle_chunked(segment);
+    }
+}
+
+impl AmpVersion for Version {
+    fn handle_value<D>(dec: &mut Dec<Self, D>, segment: BytesMut)
+    where
+        D: Default + Extend<(Bytes, Bytes)>,
+    {
+        match dec.version {
+            Version::V1 => dec.finish_value(segment.freeze()),
+            Version::V2 => dec.handle_chunked(segment),
         }
     }
 }
@@ -98,24 +335,24 @@ where
     D: Default + Extend<(Bytes, Bytes)>,
     V: AmpVersion,
 {
-    type Error = std::io::Error;
+    type Error = DecodeError;
     type Item = D;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         loop {
-            let segment = match self.decoder.decode(buf)? {
+            let segment = match self.next_segment(buf)? {
                 Some(s) => s,
                 None => return Ok(None),
             };
+            let offset = self.consumed - (AMP_LENGTH_SIZE + segment.len()) as u64;
+            self.check_limits(&segment, offset)?;
 
             match self.state {
                 State::Key => {
                     if segment.is_empty() {
-                        break Ok(Some(std::mem::take(&mut self.frame)));
+                        break Ok(Some(self.finish_box()));
                     } else {
-                        self.key = segment.freeze();
-                        self.state = State::Value;
-                        self.decoder.set_max_frame_length(AMP_VALUE_LIMIT);
+                        self.start_key(segment.freeze(), offset)?;
                     }
                 }
                 State::Value => V::handle_value(self, segment),
@@ -123,14 +360,117 @@ where
             }
         }
     }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(frame) = self.decode(buf)? {
+            return Ok(Some(frame));
+        }
+
+        let offset = self.consumed + buf.len() as u64;
+        if self.state != State::Key {
+            Err(self.fail(DecodeError::TruncatedValue { offset }))
+        } else if self.box_size > 0 || !buf.is_empty() {
+            Err(self.fail(DecodeError::TruncatedBox { offset }))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Serialize a box straight into `dst`, leaving it untouched on error.
+pub(crate) fn encode<V: AmpVersion, T: Serialize>(
+    version: V,
+    item: T,
+    dst: &mut BytesMut,
+) -> Result<(), amp_serde::Error> {
+    amp_serde::to_buf_with(version, dst, item)
+}
+
+/// Decodes and encodes whole AMP boxes, for use with `Framed`.
+#[derive(Debug)]
+pub struct AmpCodec<V, D = RawFrame> {
+    dec: Dec<V, D>,
+}
+
+impl<V: Default, D> Default for AmpCodec<V, D> {
+    fn default() -> Self {
+        AmpCodec {
+            dec: Default::default(),
+        }
+    }
+}
+
+impl<V, D> AmpCodec<V, D> {
+    pub fn with_version(version: V) -> Self {
+        AmpCodec {
+            dec: Dec::with_version(version),
+        }
+    }
+}
+
+impl<V: Default, D> AmpCodec<V, D>
+where
+    D: Default + Extend<(Bytes, Bytes)>,
+{
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_limits(limits: DecodeLimits) -> Self {
+        AmpCodec {
+            dec: Dec::with_limits(limits),
+        }
+    }
+}
+
+impl<V: Copy, D> AmpCodec<V, D>
+where
+    D: Default + Extend<(Bytes, Bytes)>,
+{
+    pub fn limits(self, limits: DecodeLimits) -> Self {
+        AmpCodec {
+            dec: self.dec.limits(limits),
+        }
+    }
+
+    pub fn duplicate_keys(self, duplicates: DuplicateKeys) -> Self {
+        AmpCodec {
+            dec: self.dec.duplicate_keys(duplicates),
+        }
+    }
+}
+
+impl<V, D> Decoder for AmpCodec<V, D>
+where
+    D: Default + Extend<(Bytes, Bytes)>,
+    V: AmpVersion,
+{
+    type Error = Error;
+    type Item = D;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.dec.decode(buf).map_err(Into::into)
+    }
+}
+
+impl<V, D, T> Encoder<T> for AmpCodec<V, D>
+where
+    V: AmpVersion,
+    T: Serialize,
+{
+    type Error = Error;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        encode(self.dec.version, item, dst).map_err(Into::into)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use amp_serde::{Request, V1};
-    use bytes::BytesMut;
+    use bytes::{Bytes, BytesMut};
     use serde::Serialize;
-    use tokio_util::codec::Decoder as _;
+    use tokio_util::codec::{Decoder as _, Encoder as _};
 
     use crate::*;
 
@@ -182,4 +522,198 @@ mod test {
 
         assert_eq!(buf, WWW_EXAMPLE);
     }
+
+    #[test]
+    fn codec_round_trip() {
+        let mut codec = AmpCodec::<V1>::new();
+        let mut buf = BytesMut::new();
+        buf.extend(WWW_EXAMPLE);
+
+        let frame = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(frame.len(), 4);
+
+        let ordered: Vec<(Bytes, Bytes)> = WWW_EXAMPLE_DEC
+            .iter()
+            .map(|(k, v)| (Bytes::from_static(k), Bytes::from_static(v)))
+            .collect();
+        let ordered: std::collections::BTreeMap<_, _> = ordered.into_iter().collect();
+        codec.encode(&ordered, &mut buf).unwrap();
+        assert_eq!(buf, WWW_EXAMPLE);
+
+        let mut too_long = RawFrame::new();
+        too_long.insert(vec![b'k'; 300].into(), Bytes::new());
+        assert!(codec.encode(too_long, &mut buf).is_err());
+        assert_eq!(buf, WWW_EXAMPLE);
+    }
+
+    #[test]
+    fn runtime_version() {
+        let mut codec = AmpCodec::<Version>::with_version(Version::V2);
+        let mut buf = BytesMut::new();
+
+        let mut frame = RawFrame::new();
+        frame.insert("blob".into(), vec![b'x'; 100_000].into());
+        codec.encode(&frame, &mut buf).unwrap();
+        assert_eq!(
+            buf,
+            amp_serde::to_bytes::<V2, _>(&frame).unwrap().as_slice()
+        );
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), frame);
+
+        let mut codec = AmpCodec::<Version>::with_version(Version::V1);
+        assert!(codec.encode(&frame, &mut buf).is_err());
+    }
+
+    #[test]
+    fn full_segment() {
+        let mut codec = AmpCodec::<V2>::new();
+        let mut buf = BytesMut::new();
+
+        // A value filling its only segment is followed by an empty one.
+        let mut frame = RawFrame::new();
+        frame.insert("blob".into(), vec![b'x'; super::AMP_VALUE_LIMIT].into());
+        codec.encode(&frame, &mut buf).unwrap();
+        codec.encode(&frame, &mut buf).unwrap();
+
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), frame);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap(), frame);
+        assert!(buf.is_empty());
+    }
+
+    fn decode_error<V: AmpVersion + Default>(limits: DecodeLimits, input: &[u8]) -> DecodeError {
+        let mut dec = Decoder::<V, Vec<_>>::with_limits(limits);
+        let mut buf = BytesMut::new();
+        buf.extend(input);
+
+        dec.decode_eof(&mut buf).unwrap_err()
+    }
+
+    #[test]
+    fn limits() {
+        let limits = DecodeLimits {
+            max_keys: 3,
+            ..Default::default()
+        };
+        assert!(matches!(
+            decode_error::<V1>(limits, WWW_EXAMPLE),
+            DecodeError::TooManyKeys {
+                limit: 3,
+                offset: 32
+            }
+        ));
+
+        let limits = DecodeLimits {
+            max_box_size: 20,
+            ..Default::default()
+        };
+        assert!(matches!(
+            decode_error::<V1>(limits, WWW_EXAMPLE),
+            DecodeError::OversizedBox {
+                limit: 20,
+                offset: 20
+            }
+        ));
+
+        #[derive(Serialize)]
+        struct Blob {
+            #[serde(with = "serde_bytes")]
+            blob: Vec<u8>,
+        }
+        let blob = amp_serde::to_bytes::<V2, _>(Blob {
+            blob: vec![0; 200_000],
+        })
+        .unwrap();
+        let limits = DecodeLimits {
+            max_value_length: 100_000,
+            ..Default::default()
+        };
+        assert!(matches!(
+            decode_error::<V2>(limits, &blob),
+            DecodeError::OversizedValue {
+                limit: 100_000,
+                offset: 65543
+            }
+        ));
+
+        let mut dec = Decoder::<V2, Vec<_>>::new();
+        let mut buf = BytesMut::new();
+        buf.extend(&blob);
+        assert_eq!(dec.decode(&mut buf).unwrap().unwrap()[0].1.len(), 200_000);
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut input = WWW_EXAMPLE[..WWW_EXAMPLE.len() - 2].to_vec();
+        input.extend(&[0x00, 0x01, b'a', 0x00, 0x01, b'7', 0x00, 0x00]);
+
+        let decode = |dec: &mut Decoder<V1, Vec<(Bytes, Bytes)>>| {
+            let mut buf = BytesMut::from(&input[..]);
+            dec.decode(&mut buf).map(|frame| {
+                frame
+                    .unwrap()
+                    .into_iter()
+                    .filter(|(k, _)| k.as_ref() == b"a")
+                    .map(|(_, v)| v)
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        let mut dec = Decoder::new();
+        assert_eq!(decode(&mut dec).unwrap(), vec!["13", "7"]);
+
+        let mut dec = Decoder::new().duplicate_keys(DuplicateKeys::FirstWins);
+        assert_eq!(decode(&mut dec).unwrap(), vec!["13"]);
+
+        let mut dec = Decoder::new().duplicate_keys(DuplicateKeys::LastWins);
+        assert_eq!(decode(&mut dec).unwrap(), vec!["7"]);
+        assert_eq!(decode(&mut dec).unwrap(), vec!["7"]);
+
+        // The last value takes the key's first place in the box.
+        let keys = |duplicates| {
+            let mut dec = Decoder::<V1, Vec<(Bytes, Bytes)>>::new().duplicate_keys(duplicates);
+            let frame = dec.decode(&mut BytesMut::from(&input[..])).unwrap();
+            frame
+                .unwrap()
+                .into_iter()
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            keys(DuplicateKeys::LastWins),
+            keys(DuplicateKeys::FirstWins)
+        );
+
+        let mut dec = Decoder::new().duplicate_keys(DuplicateKeys::Reject);
+        match decode(&mut dec) {
+            Err(DecodeError::DuplicateKey { key, offset: 39 }) => assert_eq!(key, "a"),
+            _ => unreachable!(),
+        }
+
+        let mut dec = Decoder::new().strict_keys(&[b"a"]);
+        assert!(matches!(
+            decode(&mut dec),
+            Err(DecodeError::DuplicateKey { .. })
+        ));
+    }
+
+    #[test]
+    fn malformed() {
+        let limits = DecodeLimits::default();
+
+        assert!(matches!(
+            decode_error::<V1>(limits, &WWW_EXAMPLE[..WWW_EXAMPLE.len() - 4]),
+            DecodeError::TruncatedValue { offset: 37 }
+        ));
+        assert!(matches!(
+            decode_error::<V1>(limits, &WWW_EXAMPLE[..WWW_EXAMPLE.len() - 1]),
+            DecodeError::TruncatedBox { offset: 40 }
+        ));
+
+        let mut oversized = WWW_EXAMPLE[..10].to_vec();
+        oversized.extend(&[0x01, 0x00]);
+        assert!(matches!(
+            decode_error::<V1>(limits, &oversized),
+            DecodeError::OversizedKey { offset: 10 }
+        ));
+    }
 }
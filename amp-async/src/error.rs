@@ -1,3 +1,5 @@
+use bytes::Bytes;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Ambiguous frame type")]
@@ -10,12 +12,70 @@ pub enum Error {
     InternalError,
     #[error("Serde error: {0}")]
     Serde(#[from] amp_serde::Error),
+    #[error("Decode error: {0}")]
+    Decode(DecodeError),
     #[error("Remote error: {0}")]
     Remote(RemoteError),
     #[error(transparent)]
     IO(#[from] std::io::Error),
     #[error("Invalid UTF-8: {0}")]
     InvalidUtf8(#[from] std::str::Utf8Error),
+    #[error("Child process exited with {0}")]
+    Exited(std::process::ExitStatus),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DecodeError {
+    #[error("Key longer than 255 bytes at offset {offset}")]
+    OversizedKey { offset: u64 },
+    #[error("Box larger than {limit} bytes at offset {offset}")]
+    OversizedBox { limit: usize, offset: u64 },
+    #[error("Value longer than {limit} bytes at offset {offset}")]
+    OversizedValue { limit: usize, offset: u64 },
+    #[error("Box has more than {limit} keys at offset {offset}")]
+    TooManyKeys { limit: usize, offset: u64 },
+    #[error("Duplicate key {key:?} at offset {offset}")]
+    DuplicateKey { key: Bytes, offset: u64 },
+    #[error("Stream ended inside a value at offset {offset}")]
+    TruncatedValue { offset: u64 },
+    #[error("Stream ended inside a box at offset {offset}")]
+    TruncatedBox { offset: u64 },
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+}
+
+impl DecodeError {
+    /// Stream offset of the segment where decoding failed.
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            Self::OversizedKey { offset }
+            | Self::OversizedBox { offset, .. }
+            | Self::OversizedValue { offset, .. }
+            | Self::TooManyKeys { offset, .. }
+            | Self::DuplicateKey { offset, .. }
+            | Self::TruncatedValue { offset }
+            | Self::TruncatedBox { offset } => Some(*offset),
+            Self::IO(_) => None,
+        }
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::IO(e) => Self::IO(e),
+            e => Self::Decode(e),
+        }
+    }
+}
+
+impl From<DecodeError> for std::io::Error {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::IO(e) => e,
+            e => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        }
+    }
 }
 
 #[derive(thiserror::Error, Clone, Debug)]
@@ -38,14 +98,14 @@ impl RemoteError {
     }
 }
 
-impl From<tokio::sync::oneshot::error::RecvError> for Error {
-    fn from(_error: tokio::sync::oneshot::error::RecvError) -> Self {
+impl From<futures::channel::oneshot::Canceled> for Error {
+    fn from(_error: futures::channel::oneshot::Canceled) -> Self {
         Self::InternalError
     }
 }
 
-impl<T> From<tokio::sync::mpsc::error::SendError<T>> for Error {
-    fn from(_error: tokio::sync::mpsc::error::SendError<T>) -> Self {
+impl From<futures::channel::mpsc::SendError> for Error {
+    fn from(_error: futures::channel::mpsc::SendError) -> Self {
         Self::InternalError
     }
 }
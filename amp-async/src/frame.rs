@@ -6,6 +6,9 @@ use bytes::Bytes;
 use crate::{Error, RemoteError};
 
 pub type RawFrame = HashMap<Bytes, Bytes>;
+
+/// Keys that must never be repeated, or a peer could spoof them.
+pub(crate) const RESERVED_KEYS: &[&[u8]] = &[b"_ask", b"_answer", b"_command", b"_error"];
 pub(crate) type Response = Result<RawFrame, RemoteError>;
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,524 @@
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+use std::time::{Duration, Instant};
+
+use bytes::{Bytes, BytesMut};
+use futures::future::{select, Either};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::Decoder as _;
+
+use amp_serde::{OkResponse, Request};
+
+use crate::frame::Frame;
+use crate::{Decoder, Error, RawFrame, Runtime, Version, V1};
+
+pub(crate) const NEGOTIATE_COMMAND: &str = "_negotiate";
+pub(crate) const NEGOTIATE_TAG: &[u8] = b"0";
+
+/// Settings for the capability exchange run by `Builder::negotiate`.
+#[derive(Clone, Debug)]
+pub struct Handshake {
+    features: BTreeSet<String>,
+    timeout: Duration,
+}
+
+impl Default for Handshake {
+    fn default() -> Self {
+        Handshake {
+            features: BTreeSet::new(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Handshake {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Offer an optional feature, used only if the peer offers it too.
+    pub fn feature(mut self, name: &str) -> Self {
+        self.features.insert(name.into());
+        self
+    }
+
+    /// How long to wait for the peer before falling back to V1, and
+    /// again for its answer once we have answered it.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Handshake { timeout, ..self }
+    }
+}
+
+/// The version and features both sides settled on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Negotiated {
+    pub version: Version,
+    pub features: BTreeSet<String>,
+}
+
+impl Negotiated {
+    /// What is assumed of a peer that does not negotiate.
+    fn fallback() -> Self {
+        Negotiated {
+            version: Version::V1,
+            features: BTreeSet::new(),
+        }
+    }
+
+    pub fn has_feature(&self, name: &str) -> bool {
+        self.features.contains(name)
+    }
+}
+
+struct Offer {
+    versions: BTreeSet<Version>,
+    features: BTreeSet<String>,
+}
+
+impl Offer {
+    fn to_fields(&self) -> RawFrame {
+        let versions: Vec<_> = self
+            .versions
+            .iter()
+            .map(|v| match v {
+                Version::V1 => "1",
+                Version::V2 => "2",
+            })
+            .collect();
+        let features: Vec<_> = self.features.iter().map(String::as_str).collect();
+
+        let mut fields = RawFrame::new();
+        fields.insert("versions".into(), versions.join(",").into());
+        fields.insert("features".into(), features.join(",").into());
+        fields
+    }
+
+    fn from_fields(fields: &RawFrame) -> Offer {
+        let list = |key: &str| {
+            fields
+                .get(key.as_bytes())
+                .and_then(|value| std::str::from_utf8(value).ok())
+                .unwrap_or_default()
+                .split(',')
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect::<BTreeSet<_>>()
+        };
+
+        Offer {
+            versions: list("versions")
+                .iter()
+                .filter_map(|v| match v.as_str() {
+                    "1" => Some(Version::V1),
+                    "2" => Some(Version::V2),
+                    _ => None,
+                })
+                .collect(),
+            features: list("features"),
+        }
+    }
+
+    fn agree(&self, other: &Offer) -> Negotiated {
+        Negotiated {
+            version: self
+                .versions
+                .intersection(&other.versions)
+                .max()
+                .copied()
+                .unwrap_or(Version::V1),
+            features: self
+                .features
+                .intersection(&other.features)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Exchange capabilities over a fresh connection, reading boxes with
+/// `decoder`.
+///
+/// Both sides send a `_negotiate` request and answer the other's. A
+/// peer answering with an error, sending anything else first or staying
+/// silent is taken to only speak V1. Once we have answered the peer's
+/// request it negotiates too, so it gets another `timeout` to answer
+/// ours, after which we fail rather than fall back.
+///
+/// Returns the bytes read past the handshake, which belong to the
+/// connection proper, and whether the answer to our request may still
+/// arrive on it.
+pub(crate) async fn negotiate<R, W>(
+    input: &mut R,
+    output: &mut W,
+    mut decoder: Decoder<V1, RawFrame>,
+    max_version: Version,
+    handshake: &Handshake,
+    runtime: &dyn Runtime,
+) -> Result<(Negotiated, BytesMut, bool), Error>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let ours = Offer {
+        versions: [Version::V1, Version::V2]
+            .iter()
+            .copied()
+            .filter(|v| *v <= max_version)
+            .collect(),
+        features: handshake.features.clone(),
+    };
+
+    let request = amp_serde::to_bytes::<V1, _>(Request {
+        tag: Some(Bytes::from_static(NEGOTIATE_TAG)),
+        command: NEGOTIATE_COMMAND.into(),
+        fields: ours.to_fields(),
+    })?;
+    output.write_all(&request).await?;
+    output.flush().await?;
+
+    let mut deadline = Instant::now() + handshake.timeout;
+    // Everything read, kept so that a box meant for the connection proper
+    // can be handed over untouched, and where the next box starts in it.
+    let mut read = BytesMut::new();
+    let mut start = 0;
+    // What the decoder has yet to consume.
+    let mut pending = BytesMut::new();
+    let mut answered = false;
+    let mut agreed = None;
+
+    while !(answered && agreed.is_some()) {
+        let frame = match decoder.decode(&mut pending)? {
+            Some(frame) => frame,
+            None => {
+                let before = read.len();
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                let timeout = runtime.sleep(remaining);
+                match select(Box::pin(input.read_buf(&mut read)), timeout).await {
+                    Either::Left((Ok(0), _)) => {
+                        return Err(Error::IO(std::io::ErrorKind::UnexpectedEof.into()))
+                    }
+                    Either::Left((res, _)) => res?,
+                    Either::Right(_) if answered => {
+                        return Err(Error::IO(std::io::ErrorKind::TimedOut.into()))
+                    }
+                    Either::Right(_) => {
+                        return Ok((Negotiated::fallback(), read.split_off(start), true))
+                    }
+                };
+                pending.extend_from_slice(&read[before..]);
+                continue;
+            }
+        };
+        let end = read.len() - pending.len();
+
+        match frame.try_into()? {
+            Frame::Request {
+                command,
+                tag: Some(tag),
+                ..
+            } if !answered && command == NEGOTIATE_COMMAND => {
+                // Both sides reach the same agreement from the two
+                // offers, so only the answer to our request is used.
+                let reply = amp_serde::to_bytes::<V1, _>(OkResponse {
+                    tag,
+                    fields: ours.to_fields(),
+                })?;
+                output.write_all(&reply).await?;
+                output.flush().await?;
+
+                answered = true;
+                deadline = Instant::now() + handshake.timeout;
+            }
+            Frame::Response { tag, response } if tag == NEGOTIATE_TAG && agreed.is_none() => {
+                match response {
+                    Ok(fields) => agreed = Some(ours.agree(&Offer::from_fields(&fields))),
+                    Err(_) => return Ok((Negotiated::fallback(), read.split_off(end), false)),
+                }
+            }
+            _ => {
+                return Ok((
+                    Negotiated::fallback(),
+                    read.split_off(start),
+                    agreed.is_none(),
+                ))
+            }
+        }
+
+        start = end;
+    }
+
+    Ok((agreed.unwrap(), read.split_off(start), false))
+}
+
+#[cfg(all(test, feature = "runtime-tokio"))]
+mod test {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::testing::{pipe, Echo};
+    use crate::Builder;
+
+    fn long_request() -> RawFrame {
+        let mut request = RawFrame::new();
+        request.insert("blob".into(), vec![b'x'; 70_000].into());
+        request
+    }
+
+    #[tokio::test]
+    async fn both_negotiate() {
+        let ((left_read, left_write), (right_read, right_write)) = pipe();
+
+        let (left, right) = tokio::join!(
+            Builder::default().version2().negotiate(
+                Handshake::new().feature("heartbeat").feature("compression"),
+                left_read,
+                left_write
+            ),
+            Builder::default().version2().dispatcher(Echo).negotiate(
+                Handshake::new().feature("compression"),
+                right_read,
+                right_write
+            ),
+        );
+        let (mut left, right) = (left.unwrap(), right.unwrap());
+
+        for handle in &[&left, &right] {
+            let negotiated = handle.negotiated().unwrap();
+            assert_eq!(negotiated.version, Version::V2);
+            assert!(negotiated.has_feature("compression"));
+            assert!(!negotiated.has_feature("heartbeat"));
+        }
+
+        let mut sender = left.request_sender().unwrap();
+        let reply: RawFrame = sender
+            .call_remote("Echo".into(), long_request())
+            .await
+            .unwrap();
+        assert_eq!(reply, long_request());
+
+        drop(sender);
+        left.shutdown();
+        left.join().await.unwrap();
+        right.join().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn plain_peer() {
+        let ((left_read, left_write), (right_read, right_write)) = pipe();
+
+        let right = Builder::default()
+            .dispatcher(Echo)
+            .serve(right_read, right_write);
+        let mut left = Builder::default()
+            .version2()
+            .negotiate(Handshake::new(), left_read, left_write)
+            .await
+            .unwrap();
+        assert_eq!(left.version(), Version::V1);
+
+        let mut sender = left.request_sender().unwrap();
+        let mut request = RawFrame::new();
+        request.insert("a".into(), "1".into());
+        let reply: RawFrame = sender
+            .call_remote("Echo".into(), request.clone())
+            .await
+            .unwrap();
+        assert_eq!(reply, request);
+
+        drop(sender);
+        left.shutdown();
+        left.join().await.unwrap();
+        right.join().await.unwrap();
+    }
+
+    /// Write boxes to `output` as a peer would.
+    async fn send<W: AsyncWrite + Unpin, T: serde::Serialize>(output: &mut W, frame: T) {
+        let bytes = amp_serde::to_bytes::<V1, _>(frame).unwrap();
+        output.write_all(&bytes).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn plain_peer_requests_first() {
+        let ((left_read, left_write), (right_read, mut right_write)) = pipe();
+        let mut right_read =
+            tokio_util::codec::FramedRead::new(right_read, Decoder::<V1, RawFrame>::new());
+
+        let peer = async move {
+            let mut fields = RawFrame::new();
+            fields.insert("a".into(), "1".into());
+            send(
+                &mut right_write,
+                Request {
+                    tag: Some("1".into()),
+                    command: "Echo".into(),
+                    fields,
+                },
+            )
+            .await;
+
+            let negotiate = right_read.next().await.unwrap().unwrap();
+            assert_eq!(negotiate[b"_command".as_ref()], NEGOTIATE_COMMAND);
+            send(
+                &mut right_write,
+                amp_serde::ErrorResponse {
+                    tag: NEGOTIATE_TAG.into(),
+                    code: "UNHANDLED".into(),
+                    description: "Unhandled Command".into(),
+                },
+            )
+            .await;
+
+            let reply = right_read.next().await.unwrap().unwrap();
+            assert_eq!(reply[b"_answer".as_ref()], "1");
+            assert_eq!(reply[b"a".as_ref()], "1");
+        };
+
+        let (left, ()) = tokio::join!(
+            Builder::default().version2().dispatcher(Echo).negotiate(
+                Handshake::new(),
+                left_read,
+                left_write
+            ),
+            peer,
+        );
+        let left = left.unwrap();
+        assert_eq!(left.version(), Version::V1);
+
+        // The late error for the handshake must not end the connection.
+        left.join().await.unwrap();
+    }
+
+    /// Play a peer that negotiates, sending its request after `delay`
+    /// and answering ours `delay` after that, or never if `None`.
+    fn negotiating_peer(
+        io: tokio::io::DuplexStream,
+        delay: Duration,
+        answer: bool,
+    ) -> tokio::task::JoinHandle<tokio::io::DuplexStream> {
+        let offer = Offer {
+            versions: [Version::V1, Version::V2].iter().copied().collect(),
+            features: BTreeSet::new(),
+        };
+
+        tokio::spawn(async move {
+            let mut io = tokio_util::codec::Framed::new(io, Decoder::<V1, RawFrame>::new());
+            tokio::time::sleep(delay).await;
+            send(
+                io.get_mut(),
+                Request {
+                    tag: Some("a".into()),
+                    command: NEGOTIATE_COMMAND.into(),
+                    fields: offer.to_fields(),
+                },
+            )
+            .await;
+
+            io.next().await.unwrap().unwrap();
+            let answer_to_ours = io.next().await.unwrap().unwrap();
+            assert_eq!(answer_to_ours[b"_answer".as_ref()], "a");
+
+            if !answer {
+                futures::future::pending::<()>().await;
+            }
+            tokio::time::sleep(delay).await;
+            send(
+                io.get_mut(),
+                OkResponse {
+                    tag: NEGOTIATE_TAG.into(),
+                    fields: offer.to_fields(),
+                },
+            )
+            .await;
+            io.into_inner()
+        })
+    }
+
+    #[tokio::test]
+    async fn slow_answer() {
+        let (left_io, right_io) = tokio::io::duplex(4096);
+        let (left_read, left_write) = tokio::io::split(left_io);
+        // Having answered the peer, we give it another timeout to answer.
+        let _right = negotiating_peer(right_io, Duration::from_millis(60), true);
+
+        let mut left = Builder::default()
+            .version2()
+            .negotiate(
+                Handshake::new().timeout(Duration::from_millis(100)),
+                left_read,
+                left_write,
+            )
+            .await
+            .unwrap();
+        assert_eq!(left.version(), Version::V2);
+
+        left.shutdown();
+        left.join().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn no_answer() {
+        let (left_io, right_io) = tokio::io::duplex(4096);
+        let (left_read, left_write) = tokio::io::split(left_io);
+        let _right = negotiating_peer(right_io, Duration::default(), false);
+
+        let negotiate = Builder::default().version2().negotiate(
+            Handshake::new().timeout(Duration::from_millis(20)),
+            left_read,
+            left_write,
+        );
+        match tokio::time::timeout(Duration::from_secs(5), negotiate).await {
+            Ok(Err(Error::IO(e))) => assert_eq!(e.kind(), std::io::ErrorKind::TimedOut),
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn limits() {
+        let (left_io, right_io) = tokio::io::duplex(4096);
+        let (left_read, left_write) = tokio::io::split(left_io);
+        let (_right_read, mut right_write) = tokio::io::split(right_io);
+
+        let peer = async move {
+            let mut request = RawFrame::new();
+            request.insert("blob".into(), vec![b'x'; 2000].into());
+            send(&mut right_write, request).await;
+            right_write
+        };
+        let (left, _right) = tokio::join!(
+            Builder::default()
+                .limits(crate::DecodeLimits {
+                    max_box_size: 1024,
+                    ..Default::default()
+                })
+                .negotiate(Handshake::new(), left_read, left_write),
+            peer,
+        );
+        assert!(matches!(
+            left,
+            Err(Error::Decode(crate::DecodeError::OversizedBox {
+                limit: 1024,
+                ..
+            }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn silent_peer() {
+        let ((left_read, left_write), _right) = pipe();
+
+        let mut left = Builder::default()
+            .version2()
+            .negotiate(
+                Handshake::new().timeout(Duration::from_millis(20)),
+                left_read,
+                left_write,
+            )
+            .await
+            .unwrap();
+        assert_eq!(left.version(), Version::V1);
+        assert_eq!(left.negotiated(), Some(&Negotiated::fallback()));
+
+        left.shutdown();
+        left.join().await.unwrap();
+    }
+}
@@ -4,23 +4,40 @@
 
 use bytes::{Bytes, BytesMut};
 
+pub mod blocking;
 mod codecs;
 mod error;
 mod frame;
+mod handshake;
+mod metrics;
+#[cfg(feature = "runtime-tokio")]
+pub mod process;
+mod runtime;
 mod server;
+#[cfg(feature = "runtime-tokio")]
+pub mod testing;
+mod transport;
+#[cfg(feature = "websocket")]
+mod websocket;
 
-pub use amp_serde::{AmpList, V1, V2};
-pub use codecs::Dec as Decoder;
+pub use amp_serde::{AmpList, Version, V1, V2};
+pub use codecs::{AmpCodec, Dec as Decoder, DecodeLimits, DuplicateKeys};
 pub use error::*;
 pub use frame::*;
+pub use handshake::{Handshake, Negotiated};
+pub use metrics::{LatencySummary, Metrics, MetricsSnapshot, NoopMetrics};
+pub use runtime::*;
 pub use server::*;
+pub use transport::Datagrams;
 
-pub trait AmpVersion: amp_serde::AmpEncoder + amp_serde::AmpDecoder
+/// A protocol version, either fixed at compile time by `V1` and `V2` or
+/// picked at runtime with `Version`.
+pub trait AmpVersion:
+    amp_serde::AmpEncoder + amp_serde::AmpDecoder + Into<Version> + Send + Sync + 'static
 where
     Self: Sized,
 {
-    fn handle_value<D: std::iter::Extend<(Bytes, Bytes)>>(
-        dec: &mut Decoder<Self, D>,
-        segment: BytesMut,
-    );
+    fn handle_value<D>(dec: &mut Decoder<Self, D>, segment: BytesMut)
+    where
+        D: Default + std::iter::Extend<(Bytes, Bytes)>;
 }
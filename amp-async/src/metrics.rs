@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+pub trait Metrics: Send + Sync + 'static {
+    fn request_received(&self, _command: &str) {}
+
+    fn request_sent(&self, _command: &str) {}
+
+    fn error_returned(&self, _command: &str, _code: &str) {}
+
+    fn handler_latency(&self, _command: &str, _latency: Duration) {}
+
+    fn round_trip_latency(&self, _command: &str, _latency: Duration) {}
+
+    fn bytes_read(&self, _count: usize) {}
+
+    fn bytes_written(&self, _count: usize) {}
+
+    fn pending_replies(&self, _count: usize) {}
+}
+
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+impl<M: Metrics + ?Sized> Metrics for Arc<M> {
+    fn request_received(&self, command: &str) {
+        (**self).request_received(command)
+    }
+
+    fn request_sent(&self, command: &str) {
+        (**self).request_sent(command)
+    }
+
+    fn error_returned(&self, command: &str, code: &str) {
+        (**self).error_returned(command, code)
+    }
+
+    fn handler_latency(&self, command: &str, latency: Duration) {
+        (**self).handler_latency(command, latency)
+    }
+
+    fn round_trip_latency(&self, command: &str, latency: Duration) {
+        (**self).round_trip_latency(command, latency)
+    }
+
+    fn bytes_read(&self, count: usize) {
+        (**self).bytes_read(count)
+    }
+
+    fn bytes_written(&self, count: usize) {
+        (**self).bytes_written(count)
+    }
+
+    fn pending_replies(&self, count: usize) {
+        (**self).pending_replies(count)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    pub requests_received: u64,
+    pub requests_sent: u64,
+    pub errors_returned: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub pending_replies: u64,
+    /// Requests received, by command.
+    pub received_by_command: HashMap<String, u64>,
+    /// Requests sent, by command.
+    pub sent_by_command: HashMap<String, u64>,
+    /// Errors returned to the peer, by error code.
+    pub errors_by_code: HashMap<String, u64>,
+    /// Time our dispatcher spent on requests.
+    pub handler_latency: LatencySummary,
+    /// Time from sending a request to getting its answer.
+    pub round_trip_latency: LatencySummary,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl LatencySummary {
+    fn record(&mut self, latency: Duration) {
+        self.count += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+
+        Some(self.total / self.count as u32)
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    requests_received: AtomicU64,
+    requests_sent: AtomicU64,
+    errors_returned: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    pending_replies: AtomicU64,
+    /// The counts that need a key or more than one word.
+    detail: Mutex<Detail>,
+}
+
+#[derive(Default)]
+struct Detail {
+    received_by_command: HashMap<String, u64>,
+    sent_by_command: HashMap<String, u64>,
+    errors_by_code: HashMap<String, u64>,
+    handler_latency: LatencySummary,
+    round_trip_latency: LatencySummary,
+}
+
+fn bump(counts: &mut HashMap<String, u64>, key: &str) {
+    match counts.get_mut(key) {
+        Some(count) => *count += 1,
+        None => {
+            counts.insert(key.to_owned(), 1);
+        }
+    }
+}
+
+/// Feeds both the connection counters and the user-supplied hook.
+#[derive(Clone)]
+pub(crate) struct Instruments {
+    hook: Arc<dyn Metrics>,
+    counters: Arc<Counters>,
+}
+
+impl Instruments {
+    pub(crate) fn new(hook: Arc<dyn Metrics>) -> Self {
+        Instruments {
+            hook,
+            counters: Default::default(),
+        }
+    }
+
+    pub(crate) fn request_received(&self, command: &str) {
+        self.counters
+            .requests_received
+            .fetch_add(1, Ordering::Relaxed);
+        bump(&mut self.detail().received_by_command, command);
+        self.hook.request_received(command);
+    }
+
+    pub(crate) fn request_sent(&self, command: &str) {
+        self.counters.requests_sent.fetch_add(1, Ordering::Relaxed);
+        bump(&mut self.detail().sent_by_command, command);
+        self.hook.request_sent(command);
+    }
+
+    pub(crate) fn error_returned(&self, command: &str, code: &str) {
+        self.counters
+            .errors_returned
+            .fetch_add(1, Ordering::Relaxed);
+        bump(&mut self.detail().errors_by_code, code);
+        self.hook.error_returned(command, code);
+    }
+
+    pub(crate) fn handler_latency(&self, command: &str, latency: Duration) {
+        self.detail().handler_latency.record(latency);
+        self.hook.handler_latency(command, latency);
+    }
+
+    pub(crate) fn round_trip_latency(&self, command: &str, latency: Duration) {
+        self.detail().round_trip_latency.record(latency);
+        self.hook.round_trip_latency(command, latency);
+    }
+
+    pub(crate) fn bytes_read(&self, count: usize) {
+        self.counters
+            .bytes_read
+            .fetch_add(count as u64, Ordering::Relaxed);
+        self.hook.bytes_read(count);
+    }
+
+    pub(crate) fn bytes_written(&self, count: usize) {
+        self.counters
+            .bytes_written
+            .fetch_add(count as u64, Ordering::Relaxed);
+        self.hook.bytes_written(count);
+    }
+
+    pub(crate) fn pending_replies(&self, count: usize) {
+        self.counters
+            .pending_replies
+            .store(count as u64, Ordering::Relaxed);
+        self.hook.pending_replies(count);
+    }
+
+    fn detail(&self) -> std::sync::MutexGuard<'_, Detail> {
+        // The counts stay usable even if a hook panicked mid-update.
+        self.counters
+            .detail
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub(crate) fn snapshot(&self) -> MetricsSnapshot {
+        let c = &self.counters;
+        let detail = self.detail();
+
+        MetricsSnapshot {
+            requests_received: c.requests_received.load(Ordering::Relaxed),
+            requests_sent: c.requests_sent.load(Ordering::Relaxed),
+            errors_returned: c.errors_returned.load(Ordering::Relaxed),
+            bytes_read: c.bytes_read.load(Ordering::Relaxed),
+            bytes_written: c.bytes_written.load(Ordering::Relaxed),
+            pending_replies: c.pending_replies.load(Ordering::Relaxed),
+            received_by_command: detail.received_by_command.clone(),
+            sent_by_command: detail.sent_by_command.clone(),
+            errors_by_code: detail.errors_by_code.clone(),
+            handler_latency: detail.handler_latency,
+            round_trip_latency: detail.round_trip_latency,
+        }
+    }
+}
+
+/// Counts the bytes pulled from the underlying reader.
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    instruments: Instruments,
+}
+
+impl<R> CountingReader<R> {
+    pub(crate) fn new(inner: R, instruments: Instruments) -> Self {
+        CountingReader { inner, instruments }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        let count = buf.filled().len() - before;
+        if count > 0 {
+            self.instruments.bytes_read(count);
+        }
+
+        res
+    }
+}
+
+/// Counts the bytes accepted by the underlying writer.
+pub(crate) struct CountingWriter<W> {
+    inner: W,
+    instruments: Instruments,
+}
+
+impl<W> CountingWriter<W> {
+    pub(crate) fn new(inner: W, instruments: Instruments) -> Self {
+        CountingWriter { inner, instruments }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CountingWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(count)) = res {
+            self.instruments.bytes_written(count);
+        }
+
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(all(test, feature = "runtime-tokio"))]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::*;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Metrics for Recorder {
+        fn request_received(&self, command: &str) {
+            self.0.lock().unwrap().push(format!("received {}", command));
+        }
+
+        fn request_sent(&self, command: &str) {
+            self.0.lock().unwrap().push(format!("sent {}", command));
+        }
+
+        fn error_returned(&self, command: &str, code: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("error {} {}", command, code));
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_requests() {
+        let recorder = Arc::new(Recorder::default());
+        let (mut client, server, _) = testing::pair_with(
+            Builder::default(),
+            Builder::default()
+                .dispatcher(testing::Echo)
+                .metrics(recorder.clone()),
+        );
+
+        let mut sender = client.request_sender().unwrap();
+        let mut fields = RawFrame::new();
+        fields.insert("a".into(), "1".into());
+        let reply: RawFrame = sender.call_remote("Echo".into(), fields).await.unwrap();
+        assert_eq!(reply.get(b"a".as_ref()).unwrap().as_ref(), b"1");
+
+        let res: Result<RawFrame, _> = sender.call_remote("Nope".into(), RawFrame::new()).await;
+        assert!(matches!(res, Err(Error::Remote(_))));
+
+        let client_stats = client.metrics();
+        assert_eq!(client_stats.requests_sent, 2);
+        assert_eq!(client_stats.pending_replies, 0);
+
+        assert_eq!(client_stats.sent_by_command["Echo"], 1);
+        assert_eq!(client_stats.sent_by_command["Nope"], 1);
+        assert_eq!(client_stats.round_trip_latency.count, 2);
+        assert!(
+            client_stats.round_trip_latency.max >= client_stats.round_trip_latency.mean().unwrap()
+        );
+
+        let server_stats = server.metrics();
+        assert_eq!(server_stats.requests_received, 2);
+        assert_eq!(server_stats.errors_returned, 1);
+        assert_eq!(server_stats.received_by_command["Echo"], 1);
+        assert_eq!(server_stats.received_by_command["Nope"], 1);
+        assert_eq!(server_stats.errors_by_code["UNHANDLED"], 1);
+        assert_eq!(server_stats.handler_latency.count, 2);
+        assert_eq!(server_stats.round_trip_latency, LatencySummary::default());
+        assert!(server_stats.bytes_read > 0);
+        assert!(client_stats.bytes_read > 0);
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec!["received Echo", "received Nope", "error Nope UNHANDLED"]
+        );
+
+        drop(sender);
+        client.shutdown();
+        client.join().await.unwrap();
+        server.join().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_send_is_not_counted() {
+        let recorder = Arc::new(Recorder::default());
+        let (mut client, server, _) = testing::pair_with(
+            Builder::default().metrics(recorder.clone()),
+            Builder::default(),
+        );
+        let mut sender = client.request_sender().unwrap();
+
+        client.shutdown();
+        client.join().await.unwrap();
+        server.join().await.unwrap();
+
+        let res = sender
+            .call_remote_noreply("Echo".into(), RawFrame::new())
+            .await;
+        assert!(res.is_err());
+        assert!(recorder.0.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+//! Speak AMP to a child process over its stdin and stdout.
+
+use std::process::{ExitStatus, Stdio};
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, ChildStderr, Command};
+use tokio::task::JoinHandle;
+
+use crate::{AmpVersion, Builder, Dispatcher, Error, Handle, RequestSender, Runtime, V1};
+
+/// What to do with the child's standard error.
+#[derive(Default)]
+pub enum Stderr {
+    /// Share the parent's standard error.
+    #[default]
+    Inherit,
+    /// Discard it.
+    Null,
+    /// Pass each line to a callback.
+    Lines(Box<dyn FnMut(String) + Send>),
+}
+
+/// A connection to a child process, which is killed if this is dropped
+/// before `join`.
+pub struct Process<V> {
+    handle: Handle<V>,
+    child: Child,
+    stderr: Option<JoinHandle<()>>,
+}
+
+/// Run `command` and serve its stdio with a default `Builder`.
+pub fn spawn<C: Into<Command>>(command: C) -> Result<Process<V1>, Error> {
+    spawn_with(Builder::default(), command, Stderr::default())
+}
+
+pub fn spawn_with<D, V, R, C>(
+    builder: Builder<D, V, R>,
+    command: C,
+    stderr: Stderr,
+) -> Result<Process<V>, Error>
+where
+    D: Dispatcher,
+    V: AmpVersion,
+    R: Runtime,
+    C: Into<Command>,
+{
+    let mut command = command.into();
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true);
+    command.stderr(match stderr {
+        Stderr::Inherit => Stdio::inherit(),
+        Stderr::Null => Stdio::null(),
+        Stderr::Lines(_) => Stdio::piped(),
+    });
+
+    let mut child = command.spawn()?;
+    let input = child.stdout.take().unwrap();
+    let output = child.stdin.take().unwrap();
+    let stderr = match (stderr, child.stderr.take()) {
+        (Stderr::Lines(callback), Some(pipe)) => Some(tokio::spawn(forward(pipe, callback))),
+        _ => None,
+    };
+
+    Ok(Process {
+        handle: builder.serve(input, output),
+        child,
+        stderr,
+    })
+}
+
+async fn forward(pipe: ChildStderr, mut callback: Box<dyn FnMut(String) + Send>) {
+    let mut lines = BufReader::new(pipe).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        callback(line);
+    }
+}
+
+impl<V: AmpVersion> Process<V> {
+    pub fn handle(&self) -> &Handle<V> {
+        &self.handle
+    }
+
+    pub fn request_sender(&self) -> Option<RequestSender<V>> {
+        self.handle.request_sender()
+    }
+
+    /// The OS process ID, until the child has been waited for.
+    pub fn id(&self) -> Option<u32> {
+        self.child.id()
+    }
+
+    pub async fn kill(&mut self) -> Result<(), Error> {
+        self.child.kill().await.map_err(Into::into)
+    }
+
+    /// Close the child's stdin and wait for it to exit.
+    ///
+    /// An unsuccessful exit status takes precedence over connection
+    /// errors, as it is usually what caused them.
+    pub async fn join(mut self) -> Result<ExitStatus, Error> {
+        self.handle.shutdown();
+        let res = self.handle.join().await;
+        let status = self.child.wait().await?;
+        if let Some(stderr) = self.stderr {
+            let _ = stderr.await;
+        }
+
+        if !status.success() {
+            return Err(Error::Exited(status));
+        }
+        res.map(|_| status)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::testing::Echo;
+    use crate::RawFrame;
+
+    fn sh(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        command
+    }
+
+    #[tokio::test]
+    async fn loopback() {
+        // `cat` sends our request back to us; our dispatcher answers it
+        // and `cat` routes that answer back to the pending call.
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = lines.clone();
+        let process = spawn_with(
+            Builder::default().dispatcher(Echo),
+            sh("echo starting >&2; exec cat"),
+            Stderr::Lines(Box::new(move |line| sink.lock().unwrap().push(line))),
+        )
+        .unwrap();
+        assert!(process.id().is_some());
+
+        let mut sender = process.request_sender().unwrap();
+        let mut request = RawFrame::new();
+        request.insert("a".into(), "1".into());
+        let reply: RawFrame = sender
+            .call_remote("Echo".into(), request.clone())
+            .await
+            .unwrap();
+        assert_eq!(reply, request);
+
+        drop(sender);
+        assert!(process.join().await.unwrap().success());
+        assert_eq!(*lines.lock().unwrap(), vec!["starting"]);
+    }
+
+    #[tokio::test]
+    async fn exit_status() {
+        let process = spawn_with(Builder::default(), sh("exit 3"), Stderr::Null).unwrap();
+
+        match process.join().await {
+            Err(Error::Exited(status)) => assert_eq!(status.code(), Some(3)),
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn kill() {
+        let mut process = spawn(sh("exec sleep 60")).unwrap();
+        process.kill().await.unwrap();
+
+        assert!(matches!(process.join().await, Err(Error::Exited(_))));
+    }
+}
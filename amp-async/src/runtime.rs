@@ -0,0 +1,145 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+
+/// Spawns connection tasks and provides timers, so that connections are
+/// not tied to one executor.
+pub trait Runtime: Send + Sync + 'static {
+    fn spawn(&self, future: BoxFuture<'static, ()>);
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+impl<R: Runtime + ?Sized> Runtime for Arc<R> {
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        (**self).spawn(future)
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        (**self).sleep(duration)
+    }
+}
+
+/// Runs connections on the ambient Tokio runtime.
+#[cfg(feature = "runtime-tokio")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TokioRuntime;
+
+#[cfg(feature = "runtime-tokio")]
+impl Runtime for TokioRuntime {
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        tokio::spawn(future);
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Stands in for a runtime until `Builder::runtime` sets one. A builder
+/// holding it cannot serve.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoRuntime;
+
+/// The runtime a `Builder` starts with.
+#[cfg(feature = "runtime-tokio")]
+pub type DefaultRuntime = TokioRuntime;
+
+/// The runtime a `Builder` starts with.
+#[cfg(not(feature = "runtime-tokio"))]
+pub type DefaultRuntime = NoRuntime;
+
+#[cfg(all(test, feature = "runtime-tokio"))]
+mod test {
+    use futures::channel::oneshot;
+    use futures::executor::ThreadPool;
+    use futures::FutureExt;
+    use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+
+    use super::*;
+    use crate::testing::{pipe, Echo};
+    use crate::{Builder, Handshake, RawFrame, Version};
+
+    /// A runtime with no Tokio in it.
+    struct Pool(ThreadPool);
+
+    impl Runtime for Pool {
+        fn spawn(&self, future: BoxFuture<'static, ()>) {
+            self.0.spawn_ok(future);
+        }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+            let (tx, rx) = oneshot::channel();
+            std::thread::spawn(move || {
+                std::thread::sleep(duration);
+                let _ = tx.send(());
+            });
+            Box::pin(rx.map(|_| ()))
+        }
+    }
+
+    async fn round_trip<T: Runtime + Clone>(runtime: T) {
+        let ((left_read, left_write), (right_read, right_write)) = pipe();
+        let (left_read, left_write) = (left_read.compat(), left_write.compat_write());
+        let (right_read, right_write) = (right_read.compat(), right_write.compat_write());
+
+        let right = Builder::default()
+            .version2()
+            .dispatcher(Echo)
+            .runtime(runtime.clone())
+            .serve_io(right_read, right_write);
+        let mut left = Builder::default()
+            .version2()
+            .runtime(runtime)
+            .serve_io(left_read, left_write);
+
+        let mut request = RawFrame::new();
+        request.insert("blob".into(), vec![b'x'; 70_000].into());
+
+        let mut sender = left.request_sender().unwrap();
+        let reply: RawFrame = sender
+            .call_remote("Echo".into(), request.clone())
+            .await
+            .unwrap();
+        assert_eq!(reply, request);
+
+        drop(sender);
+        left.shutdown();
+        left.join().await.unwrap();
+        right.join().await.unwrap();
+    }
+
+    async fn silent_peer<T: Runtime>(runtime: T) {
+        let ((left_read, left_write), _right) = pipe();
+
+        let mut left = Builder::default()
+            .version2()
+            .runtime(runtime)
+            .negotiate(
+                Handshake::new().timeout(Duration::from_millis(20)),
+                left_read,
+                left_write,
+            )
+            .await
+            .unwrap();
+        assert_eq!(left.version(), Version::V1);
+
+        left.shutdown();
+        left.join().await.unwrap();
+    }
+
+    #[test]
+    fn thread_pool() {
+        let pool = Arc::new(Pool(ThreadPool::new().unwrap()));
+
+        futures::executor::block_on(round_trip(pool.clone()));
+        futures::executor::block_on(silent_peer(pool));
+    }
+
+    #[tokio::test]
+    async fn tokio() {
+        round_trip(TokioRuntime).await;
+        silent_peer(TokioRuntime).await;
+    }
+}
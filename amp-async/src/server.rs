@@ -1,26 +1,35 @@
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::future::Future;
-use std::marker::PhantomData;
 use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use serde::{de::DeserializeOwned, Serialize};
 
-use futures::sink::SinkExt;
-use futures::stream::{FuturesUnordered, StreamExt, TryStreamExt};
-use futures::FutureExt;
+use futures::channel::{mpsc, oneshot};
+use futures::sink::{Sink, SinkExt};
+use futures::stream::{self, FuturesUnordered, Stream, StreamExt, TryStreamExt};
+use futures::{future, FutureExt};
 
 use async_trait::async_trait;
-use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::sync::{mpsc, oneshot};
-use tokio::task::JoinHandle;
-use tokio_util::codec::{BytesCodec, FramedRead, FramedWrite};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio_util::codec::FramedRead;
+use tokio_util::compat::{FuturesAsyncReadCompatExt, FuturesAsyncWriteCompatExt};
 
 use amp_serde::{ErrorResponse, OkResponse, Request};
 
-use crate::frame::Response;
-use crate::{AmpVersion, Decoder, Error, Frame, RawFrame, RemoteError, V1, V2};
+use crate::codecs::encode;
+
+use crate::frame::{Response, RESERVED_KEYS};
+use crate::handshake::{self, Handshake, Negotiated};
+use crate::metrics::{CountingReader, CountingWriter, Instruments};
+use crate::runtime::{DefaultRuntime, Runtime};
+use crate::transport::{decode_message, ByteSink};
+use crate::{
+    AmpVersion, DecodeLimits, Decoder, DuplicateKeys, Error, Frame, Metrics, MetricsSnapshot,
+    NoopMetrics, RawFrame, RemoteError, Version, V1, V2,
+};
 
 const QUEUE_DEPTH: usize = 32;
 
@@ -37,44 +46,216 @@ pub struct NoopDispatcher;
 
 impl Dispatcher for NoopDispatcher {}
 
-pub struct Builder<D, V> {
+pub struct Builder<D, V, R = DefaultRuntime> {
     dispatcher: D,
-    version: PhantomData<V>,
+    metrics: Arc<dyn Metrics>,
+    limits: DecodeLimits,
+    duplicates: DuplicateKeys,
+    runtime: R,
+    /// Whether the answer to our `_negotiate` may still arrive.
+    late_handshake: bool,
+    version: V,
 }
 
 impl Default for Builder<NoopDispatcher, V1> {
     fn default() -> Builder<NoopDispatcher, V1> {
         Builder {
             dispatcher: NoopDispatcher,
-            version: PhantomData,
+            metrics: Arc::new(NoopMetrics),
+            limits: Default::default(),
+            duplicates: Default::default(),
+            runtime: DefaultRuntime::default(),
+            late_handshake: false,
+            version: V1,
         }
     }
 }
 
-impl<D: Dispatcher, V> Builder<D, V>
+impl<D: Dispatcher, V, R> Builder<D, V, R>
 where
-    V: AmpVersion + Send,
+    V: AmpVersion + Send + 'static,
 {
-    pub fn version2(self) -> Builder<D, V2> {
+    pub fn version2(self) -> Builder<D, V2, R> {
+        self.with_version(V2)
+    }
+
+    /// Pick the protocol version at runtime.
+    pub fn version(self, version: Version) -> Builder<D, Version, R> {
+        self.with_version(version)
+    }
+
+    fn with_version<U>(self, version: U) -> Builder<D, U, R> {
         Builder {
             dispatcher: self.dispatcher,
-            version: PhantomData,
+            metrics: self.metrics,
+            limits: self.limits,
+            duplicates: self.duplicates,
+            runtime: self.runtime,
+            late_handshake: self.late_handshake,
+            version,
         }
     }
 
-    pub fn dispatcher<E: Dispatcher>(self, dispatcher: E) -> Builder<E, V> {
+    #[cfg(feature = "runtime-tokio")]
+    pub(crate) fn get_version(&self) -> V {
+        self.version
+    }
+
+    pub fn dispatcher<E: Dispatcher>(self, dispatcher: E) -> Builder<E, V, R> {
         Builder {
             dispatcher,
-            version: PhantomData,
+            metrics: self.metrics,
+            limits: self.limits,
+            duplicates: self.duplicates,
+            runtime: self.runtime,
+            late_handshake: self.late_handshake,
+            version: self.version,
+        }
+    }
+
+    pub fn metrics<M: Metrics>(self, metrics: M) -> Builder<D, V, R> {
+        Builder {
+            metrics: Arc::new(metrics),
+            ..self
+        }
+    }
+
+    pub fn limits(self, limits: DecodeLimits) -> Builder<D, V, R> {
+        Builder { limits, ..self }
+    }
+
+    pub fn duplicate_keys(self, duplicates: DuplicateKeys) -> Builder<D, V, R> {
+        Builder { duplicates, ..self }
+    }
+
+    /// Spawn the connection tasks on `runtime` rather than on Tokio.
+    pub fn runtime<T: Runtime>(self, runtime: T) -> Builder<D, V, T> {
+        Builder {
+            dispatcher: self.dispatcher,
+            metrics: self.metrics,
+            limits: self.limits,
+            duplicates: self.duplicates,
+            runtime,
+            late_handshake: self.late_handshake,
+            version: self.version,
         }
     }
 
-    pub fn serve<R, W>(self, input: R, output: W) -> Handle<V>
+    fn decoder<U: Copy>(&self, version: U) -> Decoder<U, RawFrame> {
+        Decoder::with_version(version)
+            .limits(self.limits)
+            .duplicate_keys(self.duplicates)
+            .strict_keys(RESERVED_KEYS)
+    }
+}
+
+/// Serving needs a runtime: without the `runtime-tokio` feature, set one
+/// with `Builder::runtime` first.
+impl<D: Dispatcher, V, R: Runtime> Builder<D, V, R>
+where
+    V: AmpVersion + Send + 'static,
+{
+    /// Serve over Tokio streams.
+    pub fn serve<I, W>(self, input: I, output: W) -> Handle<V>
     where
-        R: AsyncRead + Unpin + Send + 'static,
+        I: AsyncRead + Unpin + Send + 'static,
         W: AsyncWrite + Unpin + Send + 'static,
     {
-        serve::<R, W, D, V>(input, output, self.dispatcher)
+        let instruments = Instruments::new(self.metrics.clone());
+        let input = CountingReader::new(input, instruments.clone());
+        let input = FramedRead::new(input, self.decoder(self.version)).err_into();
+        let output = ByteSink::new(CountingWriter::new(output, instruments.clone()));
+
+        self.serve_boxes(input, output, instruments)
+    }
+
+    /// Serve over a transport that keeps message boundaries, such as a
+    /// datagram socket, sending one box per message.
+    ///
+    /// A message may hold several boxes, but a box may not span messages.
+    pub fn serve_messages<I, W>(self, input: I, output: W) -> Handle<V>
+    where
+        I: Stream<Item = std::io::Result<BytesMut>> + Unpin + Send + 'static,
+        W: Sink<Bytes, Error = std::io::Error> + Unpin + Send + 'static,
+    {
+        let instruments = Instruments::new(self.metrics.clone());
+
+        let mut decoder = self.decoder(self.version);
+        let read_instruments = instruments.clone();
+        let input = input
+            .map(move |message| {
+                let message = message?;
+                read_instruments.bytes_read(message.len());
+                let frames = decode_message(&mut decoder, message)?;
+                Ok::<_, Error>(stream::iter(frames.into_iter().map(Ok)))
+            })
+            .try_flatten();
+
+        let write_instruments = instruments.clone();
+        let output = output
+            .sink_map_err(Error::from)
+            .with(move |message: Bytes| {
+                write_instruments.bytes_written(message.len());
+                future::ready(Ok::<_, Error>(message))
+            });
+
+        self.serve_boxes(input, output, instruments)
+    }
+
+    fn serve_boxes<I, W>(self, input: I, output: W, instruments: Instruments) -> Handle<V>
+    where
+        I: Stream<Item = Result<RawFrame, Error>> + Unpin + Send + 'static,
+        W: Sink<Bytes, Error = Error> + Unpin + Send + 'static,
+    {
+        serve(
+            input,
+            output,
+            self.dispatcher,
+            instruments,
+            Arc::new(self.runtime),
+            self.late_handshake,
+            self.version,
+        )
+    }
+
+    /// Serve over `futures::io` streams, as used by async-std and smol.
+    pub fn serve_io<I, W>(self, input: I, output: W) -> Handle<V>
+    where
+        I: futures::io::AsyncRead + Unpin + Send + 'static,
+        W: futures::io::AsyncWrite + Unpin + Send + 'static,
+    {
+        self.serve(input.compat(), output.compat_write())
+    }
+
+    /// Settle on a version and features with the peer, then serve.
+    ///
+    /// Versions up to this builder's are offered.
+    pub async fn negotiate<I, W>(
+        mut self,
+        handshake: Handshake,
+        mut input: I,
+        mut output: W,
+    ) -> Result<Handle<Version>, Error>
+    where
+        I: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (negotiated, rest, late_handshake) = handshake::negotiate(
+            &mut input,
+            &mut output,
+            self.decoder(V1),
+            self.version.into(),
+            &handshake,
+            &self.runtime,
+        )
+        .await?;
+        self.late_handshake = late_handshake;
+
+        let input = std::io::Cursor::new(rest).chain(input);
+        let mut handle = self.with_version(negotiated.version).serve(input, output);
+        handle.negotiated = Some(negotiated);
+
+        Ok(handle)
     }
 }
 
@@ -97,7 +278,8 @@ pub enum State {
     Closed,
 }
 
-type _FrameMaker = Box<dyn FnOnce(Option<Bytes>) -> Result<Vec<u8>, amp_serde::Error> + Send>;
+type _FrameMaker =
+    Box<dyn FnOnce(Option<Bytes>, &mut BytesMut) -> Result<(), amp_serde::Error> + Send>;
 
 struct FrameMaker(_FrameMaker);
 
@@ -107,15 +289,17 @@ impl std::fmt::Debug for FrameMaker {
     }
 }
 
+type Reply = amp_serde::Response<RawFrame>;
+
 #[derive(Debug)]
 enum WriteCmd {
-    Reply(Bytes),
+    Reply(Reply),
     Request(FrameMaker, Option<oneshot::Sender<Response>>),
     Exit,
 }
 
 #[derive(Clone)]
-pub struct RequestSender<V>(mpsc::Sender<WriteCmd>, PhantomData<V>);
+pub struct RequestSender<V>(mpsc::Sender<WriteCmd>, Instruments, V);
 
 impl<V: AmpVersion> RequestSender<V> {
     pub async fn call_remote<Q: Serialize + Send + 'static, R: DeserializeOwned>(
@@ -124,23 +308,33 @@ impl<V: AmpVersion> RequestSender<V> {
         request: Q,
     ) -> Result<R, Error> {
         let (tx, rx) = oneshot::channel();
-
-        let frame = FrameMaker(Box::new(move |tag| {
-            amp_serde::to_bytes::<V, _>(Request {
-                tag,
-                command,
-                fields: request,
-            })
+        let version = self.2;
+
+        let name = command.clone();
+        let started = Instant::now();
+        let frame = FrameMaker(Box::new(move |tag, dst| {
+            encode(
+                version,
+                Request {
+                    tag,
+                    command,
+                    fields: request,
+                },
+                dst,
+            )
         }));
 
         self.0.send(WriteCmd::Request(frame, Some(tx))).await?;
+        self.1.request_sent(&name);
 
-        let raw_frame = rx.await?.map_err(Error::Remote)?;
+        let response = rx.await?;
+        self.1.round_trip_latency(&name, started.elapsed());
+        let raw_frame = response.map_err(Error::Remote)?;
 
         // FIXME: do this without an intermediary copy when serde gets
         // good at deserializing untagged enums with flattened structs.
-        amp_serde::to_bytes::<V, _>(raw_frame)
-            .and_then(amp_serde::from_bytes::<V, _, _>)
+        amp_serde::to_bytes_with(version, raw_frame)
+            .and_then(|bytes| amp_serde::from_bytes_with(version, bytes))
             .map_err(Into::into)
     }
 
@@ -149,15 +343,23 @@ impl<V: AmpVersion> RequestSender<V> {
         command: String,
         request: Q,
     ) -> Result<(), Error> {
-        let frame = FrameMaker(Box::new(move |tag| {
-            amp_serde::to_bytes::<V, _>(Request {
-                tag,
-                command,
-                fields: request,
-            })
+        let version = self.2;
+
+        let name = command.clone();
+        let frame = FrameMaker(Box::new(move |tag, dst| {
+            encode(
+                version,
+                Request {
+                    tag,
+                    command,
+                    fields: request,
+                },
+                dst,
+            )
         }));
 
         self.0.send(WriteCmd::Request(frame, None)).await?;
+        self.1.request_sent(&name);
 
         Ok(())
     }
@@ -165,14 +367,16 @@ impl<V: AmpVersion> RequestSender<V> {
 
 pub struct Handle<V> {
     state: Arc<RwLock<LoopState>>,
-    write_res: JoinHandle<Result<(), Error>>,
-    read_res: JoinHandle<Result<(), Error>>,
+    write_res: oneshot::Receiver<Result<(), Error>>,
+    read_res: oneshot::Receiver<Result<(), Error>>,
     write_loop_handle: Option<mpsc::Sender<WriteCmd>>,
     shutdown: Option<oneshot::Sender<()>>,
-    version: PhantomData<V>,
+    instruments: Instruments,
+    version: V,
+    negotiated: Option<Negotiated>,
 }
 
-impl<V> Handle<V> {
+impl<V: AmpVersion> Handle<V> {
     pub fn shutdown(&mut self) {
         self.write_loop_handle = None;
         if let Some(s) = self.shutdown.take() {
@@ -182,11 +386,11 @@ impl<V> Handle<V> {
 
     pub async fn join(mut self) -> Result<(), Error> {
         self.write_loop_handle = None;
-        self.write_res.await.unwrap()?;
+        (&mut self.write_res).await??;
         if let Some(s) = self.shutdown.take() {
             let _ = s.send(());
         }
-        self.read_res.await.unwrap()?;
+        (&mut self.read_res).await??;
 
         Ok(())
     }
@@ -195,7 +399,21 @@ impl<V> Handle<V> {
         self.write_loop_handle
             .as_ref()
             .cloned()
-            .map(|tx| RequestSender(tx, PhantomData))
+            .map(|tx| RequestSender(tx, self.instruments.clone(), self.version))
+    }
+
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.instruments.snapshot()
+    }
+
+    pub fn version(&self) -> Version {
+        self.version.into()
+    }
+
+    /// The outcome of `Builder::negotiate`, if the connection was set up
+    /// that way.
+    pub fn negotiated(&self) -> Option<&Negotiated> {
+        self.negotiated.as_ref()
     }
 
     pub fn state(&self) -> State {
@@ -214,12 +432,21 @@ impl<V> Handle<V> {
     }
 }
 
-fn serve<R, W, D, V>(input: R, output: W, dispatcher: D) -> Handle<V>
+/// Run a connection over streams of whole boxes.
+fn serve<R, W, D, V>(
+    input: R,
+    output: W,
+    dispatcher: D,
+    instruments: Instruments,
+    runtime: Arc<dyn Runtime>,
+    late_handshake: bool,
+    version: V,
+) -> Handle<V>
 where
-    R: AsyncRead + Unpin + Send + 'static,
-    W: AsyncWrite + Unpin + Send + 'static,
+    R: Stream<Item = Result<RawFrame, Error>> + Unpin + Send + 'static,
+    W: Sink<Bytes, Error = Error> + Unpin + Send + 'static,
     D: Dispatcher,
-    V: AmpVersion + Send,
+    V: AmpVersion + Send + 'static,
 {
     let state = Arc::new(RwLock::new(LoopState::default()));
     let (write_tx, write_rx) = mpsc::channel::<WriteCmd>(QUEUE_DEPTH);
@@ -228,18 +455,30 @@ where
 
     let read_state = state.clone();
     let write_tx2 = write_tx.clone();
-    let read_res = tokio::spawn(async move {
-        let res = read_loop::<R, D, V>(input, shutdown_rx, write_tx2, dispatcher, expect_rx).await;
+    let read_instruments = instruments.clone();
+    let (read_done, read_res) = oneshot::channel();
+    runtime.spawn(Box::pin(async move {
+        let res = read_loop(
+            input,
+            shutdown_rx,
+            write_tx2,
+            dispatcher,
+            expect_rx,
+            read_instruments,
+            late_handshake,
+        )
+        .await;
         read_state.write().unwrap().read_done = true;
-        res
-    });
+        let _ = read_done.send(res);
+    }));
 
     let write_state = state.clone();
-    let write_res = tokio::spawn(async move {
-        let res = write_loop(output, write_rx, expect_tx).await;
+    let (write_done, write_res) = oneshot::channel();
+    runtime.spawn(Box::pin(async move {
+        let res = write_loop(output, version, write_rx, expect_tx).await;
         write_state.write().unwrap().write_done = true;
-        res
-    });
+        let _ = write_done.send(res);
+    }));
 
     Handle {
         state,
@@ -247,52 +486,56 @@ where
         read_res,
         write_loop_handle: Some(write_tx),
         shutdown: Some(shutdown_tx),
-        version: PhantomData,
+        instruments,
+        version,
+        negotiated: None,
     }
 }
 
 type ReplyMap = HashMap<u64, oneshot::Sender<Response>>;
 
-async fn read_loop<R, D, V: AmpVersion>(
+async fn read_loop<R, D>(
     input: R,
-    mut shutdown: oneshot::Receiver<()>,
+    shutdown: oneshot::Receiver<()>,
     mut write_tx: mpsc::Sender<WriteCmd>,
     dispatcher: D,
     mut expect_rx: mpsc::Receiver<ExpectReply>,
+    instruments: Instruments,
+    mut late_handshake: bool,
 ) -> Result<(), Error>
 where
-    R: AsyncRead + Unpin,
+    R: Stream<Item = Result<RawFrame, Error>> + Unpin,
     D: Dispatcher,
-    V: AmpVersion,
 {
-    let codec_in = Decoder::<V, RawFrame>::new();
-    let mut input = FramedRead::new(input, codec_in);
+    let mut input = input.fuse();
+    let mut shutdown = shutdown.fuse();
     let mut reply_map = ReplyMap::new();
     let mut dispatched_requests = FuturesUnordered::new();
 
     loop {
-        tokio::select! {
+        futures::select! {
             frame = input.next() => {
                 if let Some(frame) = frame {
-                    if let Some(dr) = dispatch_frame::<D, V>(frame?, &mut reply_map, &mut write_tx, &dispatcher)? {
+                    if let Some(dr) = dispatch_frame::<D>(frame?, &mut reply_map, &mut late_handshake, &mut write_tx, &dispatcher, &instruments)? {
                         dispatched_requests.push(dr);
                     }
                 } else {
                     break;
                 }
             }
-            expect = expect_rx.recv() => {
+            expect = expect_rx.next() => {
                 if let Some(expect) = expect {
                     reply_map.insert(expect.tag, expect.reply);
+                    instruments.pending_replies(reply_map.len());
                     let _ = expect.confirm.send(());
                 } else {
                     break;
                 }
             }
-            dr = dispatched_requests.try_next(), if !dispatched_requests.is_empty() => {
+            dr = dispatched_requests.select_next_some() => {
                 dr?;
             }
-            _ = &mut shutdown => {
+            _ = shutdown => {
                 write_tx.send(WriteCmd::Exit).await?;
                 break;
             }
@@ -302,15 +545,16 @@ where
     Ok(())
 }
 
-fn dispatch_frame<'a, D, V>(
+fn dispatch_frame<'a, D>(
     frame: RawFrame,
     reply_map: &mut ReplyMap,
+    late_handshake: &mut bool,
     write_tx: &mut mpsc::Sender<WriteCmd>,
     dispatcher: &'a D,
+    instruments: &Instruments,
 ) -> Result<Option<impl Future<Output = Result<(), Error>> + 'a>, Error>
 where
     D: Dispatcher,
-    V: AmpVersion,
 {
     match frame.try_into()? {
         Frame::Request {
@@ -318,29 +562,39 @@ where
             command,
             fields,
         } => Ok(Some(match tag {
-            None => async move {
-                dispatcher
-                    .dispatch_noreply(std::str::from_utf8(&command)?, fields)
-                    .await;
+            None => {
+                let instruments = instruments.clone();
+                async move {
+                    let command = std::str::from_utf8(&command)?;
+                    instruments.request_received(command);
+                    let started = Instant::now();
+                    dispatcher.dispatch_noreply(command, fields).await;
+                    instruments.handler_latency(command, started.elapsed());
 
-                Ok(())
+                    Ok(())
+                }
+                .left_future()
             }
-            .left_future(),
             Some(tag) => {
-                let write_tx = write_tx.clone();
+                let mut write_tx = write_tx.clone();
+                let instruments = instruments.clone();
                 async move {
-                    let reply = match dispatcher
-                        .dispatch(std::str::from_utf8(&command)?, fields)
-                        .await
-                    {
-                        Ok(reply) => {
-                            amp_serde::to_bytes::<V, _>(OkResponse { tag, fields: reply })?
+                    let command = std::str::from_utf8(&command)?;
+                    instruments.request_received(command);
+                    let started = Instant::now();
+                    let reply = dispatcher.dispatch(command, fields).await;
+                    instruments.handler_latency(command, started.elapsed());
+
+                    let reply = match reply {
+                        Ok(reply) => Ok(OkResponse { tag, fields: reply }),
+                        Err(e) => {
+                            instruments.error_returned(command, &e.code);
+                            Err(ErrorResponse {
+                                tag,
+                                code: e.code,
+                                description: e.description,
+                            })
                         }
-                        Err(e) => amp_serde::to_bytes::<V, _>(ErrorResponse {
-                            tag,
-                            code: e.code,
-                            description: e.description,
-                        })?,
                     };
                     write_tx.send(WriteCmd::Reply(reply.into())).await?;
                     Ok(())
@@ -349,12 +603,20 @@ where
             }
         })),
 
+        // A peer that does not negotiate may answer our `_negotiate` after
+        // the handshake gave up on it.
+        Frame::Response { tag, .. } if *late_handshake && tag == handshake::NEGOTIATE_TAG => {
+            *late_handshake = false;
+            Ok(None)
+        }
+
         Frame::Response { tag, response } => {
             let reply_tx = std::str::from_utf8(&tag)
                 .ok()
                 .and_then(|tag_str| u64::from_str_radix(tag_str, 16).ok())
                 .and_then(|tag_u64| reply_map.remove(&tag_u64))
                 .ok_or(Error::UnmatchedReply)?;
+            instruments.pending_replies(reply_map.len());
 
             reply_tx.send(response).map_err(|_| Error::InternalError)?;
             Ok(None)
@@ -362,21 +624,24 @@ where
     }
 }
 
-async fn write_loop<W>(
-    output: W,
+async fn write_loop<W, V>(
+    mut output: W,
+    version: V,
     mut input: mpsc::Receiver<WriteCmd>,
-    expect_tx: mpsc::Sender<ExpectReply>,
+    mut expect_tx: mpsc::Sender<ExpectReply>,
 ) -> Result<(), Error>
 where
-    W: AsyncWrite + Unpin,
+    W: Sink<Bytes, Error = Error> + Unpin,
+    V: AmpVersion,
 {
-    let mut output = FramedWrite::new(output, BytesCodec::new());
+    let mut buf = BytesMut::new();
     let mut seqno: u64 = 0;
 
-    while let Some(msg) = input.recv().await {
+    while let Some(msg) = input.next().await {
         match msg {
             WriteCmd::Reply(frame) => {
-                output.send(frame).await?;
+                encode(version, frame, &mut buf)?;
+                output.send(buf.split().freeze()).await?;
             }
             WriteCmd::Request(request, reply) => {
                 let tag = if let Some(reply) = reply {
@@ -398,12 +663,17 @@ where
                     None
                 };
 
-                let out = request.0(tag)?.into();
-                output.send(out).await?;
+                request.0(tag, &mut buf)?;
+                output.send(buf.split().freeze()).await?;
             }
             WriteCmd::Exit => break,
         }
     }
 
+    // Let the peer know we are done, e.g. with a WebSocket close frame.
+    // Everything was already flushed, so a peer that has gone away by now
+    // is no reason to fail.
+    let _ = output.close().await;
+
     Ok(())
 }
@@ -0,0 +1,248 @@
+//! Helpers for exercising dispatchers without processes or sockets.
+
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use tokio::io::{AsyncWrite, DuplexStream, ReadHalf, WriteHalf};
+use tokio_util::codec::Decoder as _;
+
+use crate::{
+    AmpVersion, Builder, Decoder, Dispatcher, Handle, NoopDispatcher, RawFrame, RemoteError,
+    Runtime, Version, V1,
+};
+
+mod fault;
+mod mock;
+
+pub use fault::{Faults, FaultyIo};
+pub use mock::{MockPeer, ScriptError};
+
+const PIPE_SIZE: usize = 64 * 1024;
+
+/// Boxes written by each side of a connected pair, in order.
+#[derive(Clone, Default)]
+pub struct Transcript {
+    left: Arc<Mutex<Vec<RawFrame>>>,
+    right: Arc<Mutex<Vec<RawFrame>>>,
+}
+
+impl Transcript {
+    /// Boxes sent by the left handle.
+    pub fn left(&self) -> Vec<RawFrame> {
+        self.left.lock().unwrap().clone()
+    }
+
+    /// Boxes sent by the right handle.
+    pub fn right(&self) -> Vec<RawFrame> {
+        self.right.lock().unwrap().clone()
+    }
+}
+
+/// Answers `Echo` requests with their own fields. Other commands are
+/// unhandled.
+pub struct Echo;
+
+#[async_trait]
+impl Dispatcher for Echo {
+    async fn dispatch(&self, command: &str, frame: RawFrame) -> Result<RawFrame, RemoteError> {
+        match command {
+            "Echo" => Ok(frame),
+            _ => Err(RemoteError::new(Some("UNHANDLED"), Option::<&str>::None)),
+        }
+    }
+}
+
+type Halves = (ReadHalf<DuplexStream>, WriteHalf<DuplexStream>);
+
+/// Both ends of an in-memory pipe, each split for `Builder::serve`.
+pub fn pipe() -> (Halves, Halves) {
+    let (left, right) = tokio::io::duplex(PIPE_SIZE);
+
+    (tokio::io::split(left), tokio::io::split(right))
+}
+
+/// Two connected handles with no-op dispatchers speaking V1.
+pub fn pair() -> (Handle<V1>, Handle<V1>) {
+    let (left, right, _) = pair_with(Builder::default(), Builder::default());
+    (left, right)
+}
+
+/// Connect two builders back to back, recording every box exchanged.
+pub fn pair_with<DL, VL, RL, DR, VR, RR>(
+    left: Builder<DL, VL, RL>,
+    right: Builder<DR, VR, RR>,
+) -> (Handle<VL>, Handle<VR>, Transcript)
+where
+    DL: Dispatcher,
+    VL: AmpVersion + Send + 'static,
+    RL: Runtime,
+    DR: Dispatcher,
+    VR: AmpVersion + Send + 'static,
+    RR: Runtime,
+{
+    let transcript = Transcript::default();
+    let ((left_read, left_write), (right_read, right_write)) = pipe();
+
+    let left_tap = Tap::new(left.get_version(), left_write, transcript.left.clone());
+    let left = left.serve(left_read, left_tap);
+    let right_tap = Tap::new(right.get_version(), right_write, transcript.right.clone());
+    let right = right.serve(right_read, right_tap);
+
+    (left, right, transcript)
+}
+
+/// Like `pair`, with a dispatcher serving requests on the right side.
+pub fn pair_dispatching<D: Dispatcher>(dispatcher: D) -> (Handle<V1>, Handle<V1>, Transcript) {
+    pair_with(
+        Builder::<NoopDispatcher, V1>::default(),
+        Builder::default().dispatcher(dispatcher),
+    )
+}
+
+/// Panic unless `frame` holds exactly the given key/value pairs.
+pub fn assert_box(frame: &RawFrame, expected: &[(&str, &str)]) {
+    let expected: RawFrame = expected
+        .iter()
+        .map(|(k, v)| (k.as_bytes().to_vec().into(), v.as_bytes().to_vec().into()))
+        .collect();
+
+    assert_eq!(frame, &expected, "unexpected AMP box");
+}
+
+/// Decodes a copy of everything written through it.
+struct Tap {
+    inner: WriteHalf<DuplexStream>,
+    buf: BytesMut,
+    decoder: Decoder<Version, RawFrame>,
+    frames: Arc<Mutex<Vec<RawFrame>>>,
+}
+
+impl Tap {
+    fn new<V: AmpVersion>(
+        version: V,
+        inner: WriteHalf<DuplexStream>,
+        frames: Arc<Mutex<Vec<RawFrame>>>,
+    ) -> Self {
+        Tap {
+            inner,
+            buf: BytesMut::new(),
+            decoder: Decoder::with_version(version.into()),
+            frames,
+        }
+    }
+}
+
+impl AsyncWrite for Tap {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, data);
+
+        if let Poll::Ready(Ok(count)) = res {
+            let this = &mut *self;
+            this.buf.extend_from_slice(&data[..count]);
+            while let Some(frame) = this.decoder.decode(&mut this.buf)? {
+                this.frames.lock().unwrap().push(frame);
+            }
+        }
+
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Double;
+
+    #[async_trait]
+    impl Dispatcher for Double {
+        async fn dispatch(&self, _command: &str, frame: RawFrame) -> Result<RawFrame, RemoteError> {
+            let n: i64 = std::str::from_utf8(&frame[b"n".as_ref()])
+                .unwrap()
+                .parse()
+                .unwrap();
+            let mut out = RawFrame::new();
+            out.insert("n".into(), format!("{}", n * 2).into());
+            Ok(out)
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let (mut left, right, transcript) = pair_dispatching(Double);
+
+        let mut sender = left.request_sender().unwrap();
+        let mut request = RawFrame::new();
+        request.insert("n".into(), "21".into());
+        let reply: RawFrame = sender.call_remote("Double".into(), request).await.unwrap();
+        assert_box(&reply, &[("n", "42")]);
+
+        assert_box(
+            &transcript.left()[0],
+            &[("_ask", "1"), ("_command", "Double"), ("n", "21")],
+        );
+        assert_box(&transcript.right()[0], &[("_answer", "1"), ("n", "42")]);
+
+        drop(sender);
+        left.shutdown();
+        left.join().await.unwrap();
+        right.join().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn version2_pair() {
+        let (mut left, right, _) = pair_with(
+            Builder::default().version2(),
+            Builder::default().version2().dispatcher(Double),
+        );
+
+        let mut sender = left.request_sender().unwrap();
+        let mut request = RawFrame::new();
+        request.insert("n".into(), "2".into());
+        let reply: RawFrame = sender.call_remote("Double".into(), request).await.unwrap();
+        assert_box(&reply, &[("n", "4")]);
+
+        drop(sender);
+        left.shutdown();
+        left.join().await.unwrap();
+        right.join().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn runtime_version() {
+        let (mut left, right, transcript) = pair_with(
+            Builder::default().version(Version::V2),
+            Builder::default().version2().dispatcher(Double),
+        );
+        assert_eq!(left.version(), Version::V2);
+        assert_eq!(right.version(), Version::V2);
+
+        let mut sender = left.request_sender().unwrap();
+        let mut request = RawFrame::new();
+        request.insert("n".into(), "8".into());
+        request.insert("pad".into(), vec![b'x'; 70_000].into());
+        let reply: RawFrame = sender.call_remote("Double".into(), request).await.unwrap();
+        assert_box(&reply, &[("n", "16")]);
+        assert_eq!(transcript.left()[0][b"pad".as_ref()].len(), 70_000);
+
+        drop(sender);
+        left.shutdown();
+        left.join().await.unwrap();
+        right.join().await.unwrap();
+    }
+}
@@ -0,0 +1,426 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::ready;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, Sleep};
+
+/// A schedule of transport faults, replayed identically for a given seed.
+#[derive(Clone, Debug)]
+pub struct Faults {
+    seed: u64,
+    latency: Duration,
+    bytes_per_sec: Option<u64>,
+    max_chunk: Option<usize>,
+    random_chunks: bool,
+    corrupt_one_in: Option<u32>,
+    disconnect_after: Option<usize>,
+}
+
+impl Faults {
+    pub fn new(seed: u64) -> Self {
+        Faults {
+            seed,
+            latency: Duration::default(),
+            bytes_per_sec: None,
+            max_chunk: None,
+            random_chunks: false,
+            corrupt_one_in: None,
+            disconnect_after: None,
+        }
+    }
+
+    /// Delay every read and write by `latency`.
+    pub fn latency(self, latency: Duration) -> Self {
+        Faults { latency, ..self }
+    }
+
+    /// Pace the transfer to at most `bytes_per_sec`.
+    pub fn bandwidth(self, bytes_per_sec: u64) -> Self {
+        Faults {
+            bytes_per_sec: Some(bytes_per_sec.max(1)),
+            ..self
+        }
+    }
+
+    /// Transfer at most `max_chunk` bytes per operation; 1 reads and
+    /// writes a byte at a time.
+    pub fn max_chunk(self, max_chunk: usize) -> Self {
+        Faults {
+            max_chunk: Some(max_chunk.max(1)),
+            ..self
+        }
+    }
+
+    /// Pick each operation's length at random, up to the chunk limit.
+    pub fn random_chunks(self) -> Self {
+        Faults {
+            random_chunks: true,
+            ..self
+        }
+    }
+
+    /// Flip bits in roughly one byte out of `one_in`.
+    pub fn corrupt(self, one_in: u32) -> Self {
+        Faults {
+            corrupt_one_in: Some(one_in.max(1)),
+            ..self
+        }
+    }
+
+    /// Fail with `ConnectionReset` once `bytes` have gone through.
+    pub fn disconnect_after(self, bytes: usize) -> Self {
+        Faults {
+            disconnect_after: Some(bytes),
+            ..self
+        }
+    }
+
+    pub fn wrap<T>(&self, inner: T) -> FaultyIo<T> {
+        FaultyIo {
+            inner,
+            rng: Rng::new(self.seed),
+            faults: self.clone(),
+            delay: None,
+            waited: false,
+            owed: Duration::default(),
+            transferred: 0,
+            read_len: None,
+            write_len: None,
+            corrupted: None,
+        }
+    }
+}
+
+/// Deterministic xorshift generator, good enough for fault schedules.
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Zero is a fixed point of xorshift.
+        Rng(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+/// An `AsyncRead`/`AsyncWrite` wrapper injecting faults from a `Faults`
+/// schedule.
+pub struct FaultyIo<T> {
+    inner: T,
+    rng: Rng,
+    faults: Faults,
+    delay: Option<Pin<Box<Sleep>>>,
+    waited: bool,
+    owed: Duration,
+    transferred: usize,
+    // What was drawn for an operation the inner IO is not ready for,
+    // so that retrying it does not advance the schedule.
+    read_len: Option<usize>,
+    write_len: Option<usize>,
+    /// The bytes last offered for writing and their corrupted copy.
+    corrupted: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl<T> FaultyIo<T> {
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn poll_delay(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.waited {
+            return Poll::Ready(());
+        }
+
+        if self.delay.is_none() {
+            let duration = self.faults.latency + std::mem::take(&mut self.owed);
+            if duration == Duration::default() {
+                self.waited = true;
+                return Poll::Ready(());
+            }
+            self.delay = Some(Box::pin(sleep(duration)));
+        }
+
+        ready!(self.delay.as_mut().unwrap().as_mut().poll(cx));
+        self.delay = None;
+        self.waited = true;
+        Poll::Ready(())
+    }
+
+    fn chunk_len(&mut self, available: usize) -> io::Result<usize> {
+        let mut len = available;
+
+        if let Some(limit) = self.faults.disconnect_after {
+            if self.transferred >= limit && available > 0 {
+                return Err(io::ErrorKind::ConnectionReset.into());
+            }
+            len = len.min(limit - self.transferred);
+        }
+        if let Some(max_chunk) = self.faults.max_chunk {
+            len = len.min(max_chunk);
+        }
+        if self.faults.random_chunks && len > 1 {
+            len = 1 + self.rng.below(len as u64) as usize;
+        }
+
+        Ok(len)
+    }
+
+    fn corrupt(&mut self, data: &mut [u8]) {
+        if let Some(one_in) = self.faults.corrupt_one_in {
+            for byte in data {
+                if self.rng.below(one_in.into()) == 0 {
+                    *byte ^= 1 + self.rng.below(0xff) as u8;
+                }
+            }
+        }
+    }
+
+    fn done(&mut self, count: usize) {
+        self.waited = false;
+        self.transferred += count;
+        if let Some(bytes_per_sec) = self.faults.bytes_per_sec {
+            self.owed = Duration::from_secs_f64(count as f64 / bytes_per_sec as f64);
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for FaultyIo<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        ready!(this.poll_delay(cx));
+
+        let len = match this.read_len {
+            Some(len) => len,
+            None => this.chunk_len(buf.remaining())?,
+        };
+        this.read_len = Some(len);
+        let mut sub = ReadBuf::new(buf.initialize_unfilled_to(len.min(buf.remaining())));
+        let res = ready!(Pin::new(&mut this.inner).poll_read(cx, &mut sub));
+        this.read_len = None;
+        res?;
+
+        let count = sub.filled().len();
+        this.corrupt(sub.filled_mut());
+        buf.advance(count);
+        this.done(count);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for FaultyIo<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        ready!(this.poll_delay(cx));
+
+        let len = match this.write_len {
+            Some(len) => len.min(data.len()),
+            None => this.chunk_len(data.len())?,
+        };
+        this.write_len = Some(len);
+        if this.faults.corrupt_one_in.is_some() {
+            let data = &data[..len];
+            match &mut this.corrupted {
+                // A retry of the same bytes, or fewer of them, sends the
+                // same corruption.
+                Some((original, copy)) if original.starts_with(data) => {
+                    original.truncate(len);
+                    copy.truncate(len);
+                }
+                _ => {
+                    let mut copy = data.to_vec();
+                    this.corrupt(&mut copy);
+                    this.corrupted = Some((data.to_vec(), copy));
+                }
+            }
+        }
+
+        let chunk = match &this.corrupted {
+            Some((_, copy)) => copy,
+            None => &data[..len],
+        };
+        let res = ready!(Pin::new(&mut this.inner).poll_write(cx, chunk));
+        this.write_len = None;
+        this.corrupted = None;
+        let count = res?;
+        this.done(count);
+
+        Poll::Ready(Ok(count))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::testing::{pipe, Echo};
+    use crate::{Builder, Error, RawFrame};
+
+    fn long_request() -> RawFrame {
+        let mut request = RawFrame::new();
+        request.insert("blob".into(), vec![b'x'; 150_000].into());
+        request.insert("a".into(), "1".into());
+        request
+    }
+
+    #[tokio::test]
+    async fn split_segments() {
+        let faults = Faults::new(7).max_chunk(1000).random_chunks();
+        let ((client_read, client_write), (server_read, server_write)) = pipe();
+
+        let server = Builder::default()
+            .version2()
+            .dispatcher(Echo)
+            .serve(faults.wrap(server_read), faults.wrap(server_write));
+        let mut client = Builder::default()
+            .version2()
+            .serve(faults.wrap(client_read), faults.wrap(client_write));
+
+        let mut sender = client.request_sender().unwrap();
+        let reply: RawFrame = sender
+            .call_remote("Echo".into(), long_request())
+            .await
+            .unwrap();
+        assert_eq!(reply, long_request());
+
+        drop(sender);
+        client.shutdown();
+        client.join().await.unwrap();
+        server.join().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn disconnect() {
+        let faults = Faults::new(1).disconnect_after(100);
+        let ((client_read, client_write), (server_read, server_write)) = pipe();
+
+        let _server = Builder::default()
+            .dispatcher(Echo)
+            .serve(server_read, server_write);
+        let client = Builder::default().serve(client_read, faults.wrap(client_write));
+
+        let mut request = RawFrame::new();
+        request.insert("blob".into(), vec![b'x'; 500].into());
+
+        let mut sender = client.request_sender().unwrap();
+        let res: Result<RawFrame, _> = sender.call_remote("Echo".into(), request).await;
+        assert!(matches!(res, Err(Error::InternalError)));
+
+        drop(sender);
+        match client.join().await {
+            Err(Error::IO(e)) => assert_eq!(e.kind(), io::ErrorKind::ConnectionReset),
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn seeded_corruption() {
+        async fn run(seed: u64) -> Vec<u8> {
+            let mut out = Faults::new(seed).corrupt(10).wrap(Vec::new());
+            out.write_all(&[0; 1000]).await.unwrap();
+            out.into_inner()
+        }
+
+        let first = run(3).await;
+        assert_eq!(first, run(3).await);
+        assert_ne!(first, run(4).await);
+        assert!(first.iter().any(|b| *b != 0));
+    }
+
+    /// Refuses every other write, like a full socket buffer.
+    struct Stalling {
+        out: Vec<u8>,
+        stall: bool,
+    }
+
+    impl AsyncWrite for Stalling {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            data: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.stall = !self.stall;
+            if self.stall {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            self.out.extend_from_slice(data);
+            Poll::Ready(Ok(data.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn shorter_retry() {
+        let mut io = Faults::new(5).corrupt(2).wrap(Stalling {
+            out: Vec::new(),
+            stall: false,
+        });
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+
+        let data = [0; 100];
+        assert!(Pin::new(&mut io).poll_write(&mut cx, &data).is_pending());
+        match Pin::new(&mut io).poll_write(&mut cx, &data[..10]) {
+            Poll::Ready(Ok(count)) => assert_eq!(count, 10),
+            _ => unreachable!(),
+        }
+        assert_eq!(io.into_inner().out.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn replay_under_backpressure() {
+        let faults = Faults::new(5).max_chunk(64).random_chunks().corrupt(10);
+        let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+
+        let mut plain = faults.wrap(Vec::new());
+        plain.write_all(&data).await.unwrap();
+
+        let mut stalling = faults.wrap(Stalling {
+            out: Vec::new(),
+            stall: false,
+        });
+        stalling.write_all(&data).await.unwrap();
+
+        assert_eq!(stalling.into_inner().out, plain.into_inner());
+    }
+}
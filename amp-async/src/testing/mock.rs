@@ -0,0 +1,334 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::stream::StreamExt;
+use tokio::io::{AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio::task::JoinHandle;
+use tokio_util::codec::FramedRead;
+
+use amp_serde::{ErrorResponse, OkResponse};
+
+use crate::{
+    AmpVersion, Builder, DecodeError, Decoder, Dispatcher, Handle, RawFrame, Runtime, Version, V1,
+    V2,
+};
+
+use super::pipe;
+
+type Fields = BTreeMap<Bytes, Bytes>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ScriptError {
+    #[error("Expected {expected}, got {got:?}")]
+    Unexpected { expected: String, got: RawFrame },
+    #[error("Expected {0}, but the connection was closed")]
+    Closed(String),
+    #[error("Expected the connection to close, got {0:?}")]
+    NotClosed(RawFrame),
+    #[error("No request to reply to")]
+    NoPendingRequest,
+    #[error("Serde error: {0}")]
+    Serde(#[from] amp_serde::Error),
+    #[error("Decode error: {0}")]
+    Decode(#[from] DecodeError),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+}
+
+#[derive(Debug)]
+enum Step {
+    Expect { command: String, fields: RawFrame },
+    Reply(Fields),
+    ReplyError { code: String, description: String },
+    SendBox(Fields),
+    SendRaw(Bytes),
+    Delay(Duration),
+    Close,
+    ExpectClose,
+}
+
+/// Plays the remote side of a connection from a script.
+///
+/// Steps run in order; the connection is dropped once the script
+/// runs out.
+#[derive(Debug)]
+pub struct MockPeer<V> {
+    steps: Vec<Step>,
+    version: V,
+}
+
+impl Default for MockPeer<V1> {
+    fn default() -> MockPeer<V1> {
+        MockPeer {
+            steps: Vec::new(),
+            version: V1,
+        }
+    }
+}
+
+fn to_fields(fields: &[(&str, &str)]) -> Fields {
+    fields
+        .iter()
+        .map(|(k, v)| (k.as_bytes().to_vec().into(), v.as_bytes().to_vec().into()))
+        .collect()
+}
+
+impl<V> MockPeer<V>
+where
+    V: AmpVersion + Send + 'static,
+{
+    pub fn version2(self) -> MockPeer<V2> {
+        MockPeer {
+            steps: self.steps,
+            version: V2,
+        }
+    }
+
+    pub fn version(self, version: Version) -> MockPeer<Version> {
+        MockPeer {
+            steps: self.steps,
+            version,
+        }
+    }
+
+    fn step(mut self, step: Step) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Wait for a request for `command` carrying exactly `fields`.
+    pub fn expect(self, command: &str, fields: &[(&str, &str)]) -> Self {
+        self.step(Step::Expect {
+            command: command.into(),
+            fields: to_fields(fields).into_iter().collect(),
+        })
+    }
+
+    /// Answer the last expected request.
+    pub fn reply(self, fields: &[(&str, &str)]) -> Self {
+        self.step(Step::Reply(to_fields(fields)))
+    }
+
+    /// Answer the last expected request with an error.
+    pub fn reply_error(self, code: &str, description: &str) -> Self {
+        self.step(Step::ReplyError {
+            code: code.into(),
+            description: description.into(),
+        })
+    }
+
+    /// Send an arbitrary box, reserved keys included.
+    pub fn send_box(self, fields: &[(&str, &str)]) -> Self {
+        self.step(Step::SendBox(to_fields(fields)))
+    }
+
+    /// Send bytes verbatim, well-formed or not.
+    pub fn send_raw<B: Into<Bytes>>(self, bytes: B) -> Self {
+        self.step(Step::SendRaw(bytes.into()))
+    }
+
+    pub fn delay(self, duration: Duration) -> Self {
+        self.step(Step::Delay(duration))
+    }
+
+    /// Shut down the write side of the connection.
+    pub fn close(self) -> Self {
+        self.step(Step::Close)
+    }
+
+    /// Wait for the other side to close the connection.
+    pub fn expect_close(self) -> Self {
+        self.step(Step::ExpectClose)
+    }
+
+    /// Connect the script to a handle served by `builder`.
+    pub fn serve<D: Dispatcher, R: Runtime>(
+        self,
+        builder: Builder<D, V, R>,
+    ) -> (Handle<V>, JoinHandle<Result<(), ScriptError>>) {
+        let ((local_read, local_write), (remote_read, remote_write)) = pipe();
+
+        let handle = builder.serve(local_read, local_write);
+        let script = tokio::spawn(run(self.version, self.steps, remote_read, remote_write));
+
+        (handle, script)
+    }
+}
+
+async fn run<V: AmpVersion>(
+    version: V,
+    steps: Vec<Step>,
+    input: ReadHalf<DuplexStream>,
+    mut output: WriteHalf<DuplexStream>,
+) -> Result<(), ScriptError> {
+    let mut input = FramedRead::new(input, Decoder::<V, RawFrame>::with_version(version));
+    let mut pending: Option<Bytes> = None;
+
+    for step in steps {
+        match step {
+            Step::Expect { command, fields } => {
+                let mut frame = match input.next().await {
+                    Some(frame) => frame?,
+                    None => return Err(ScriptError::Closed(command)),
+                };
+
+                let got = frame.remove(b"_command".as_ref());
+                let tag = frame.remove(b"_ask".as_ref());
+                if got.as_deref() != Some(command.as_bytes()) || frame != fields {
+                    if let Some(got) = got {
+                        frame.insert("_command".into(), got);
+                    }
+                    return Err(ScriptError::Unexpected {
+                        expected: command,
+                        got: frame,
+                    });
+                }
+                pending = tag;
+            }
+            Step::Reply(fields) => {
+                let tag = pending.take().ok_or(ScriptError::NoPendingRequest)?;
+                let out = amp_serde::to_bytes_with(version, OkResponse { tag, fields })?;
+                output.write_all(&out).await?;
+            }
+            Step::ReplyError { code, description } => {
+                let tag = pending.take().ok_or(ScriptError::NoPendingRequest)?;
+                let out = amp_serde::to_bytes_with(
+                    version,
+                    ErrorResponse {
+                        tag,
+                        code,
+                        description,
+                    },
+                )?;
+                output.write_all(&out).await?;
+            }
+            Step::SendBox(fields) => {
+                output
+                    .write_all(&amp_serde::to_bytes_with(version, fields)?)
+                    .await?;
+            }
+            Step::SendRaw(bytes) => output.write_all(&bytes).await?,
+            Step::Delay(duration) => tokio::time::sleep(duration).await,
+            Step::Close => output.shutdown().await?,
+            Step::ExpectClose => {
+                if let Some(frame) = input.next().await {
+                    return Err(ScriptError::NotClosed(frame?));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::Error;
+
+    #[derive(Serialize)]
+    struct Sum {
+        a: i64,
+        b: i64,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Total {
+        total: i64,
+    }
+
+    #[tokio::test]
+    async fn scripted_calls() {
+        let (mut handle, script) = MockPeer::default()
+            .expect("Sum", &[("a", "1"), ("b", "2")])
+            .delay(Duration::from_millis(10))
+            .reply(&[("total", "3")])
+            .expect("Sum", &[("a", "1"), ("b", "-1")])
+            .reply_error("ZERO", "no zeroes allowed")
+            .expect_close()
+            .serve(Builder::default());
+
+        let mut sender = handle.request_sender().unwrap();
+        let total: Total = sender
+            .call_remote("Sum".into(), Sum { a: 1, b: 2 })
+            .await
+            .unwrap();
+        assert_eq!(total, Total { total: 3 });
+
+        let res: Result<Total, _> = sender.call_remote("Sum".into(), Sum { a: 1, b: -1 }).await;
+        match res {
+            Err(Error::Remote(e)) => assert_eq!(e.to_string(), r#""ZERO": "no zeroes allowed""#),
+            _ => unreachable!(),
+        }
+
+        drop(sender);
+        handle.shutdown();
+        handle.join().await.unwrap();
+        script.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn unmatched_reply() {
+        let (handle, script) = MockPeer::default()
+            .send_box(&[("_answer", "ff"), ("total", "0")])
+            .serve(Builder::default());
+
+        script.await.unwrap().unwrap();
+        match handle.join().await {
+            Err(Error::UnmatchedReply) => (),
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_box() {
+        let (handle, script) = MockPeer::default()
+            .send_raw(&b"\x00\x01a\x00\x01b\x01\x00"[..])
+            .serve(Builder::default());
+
+        script.await.unwrap().unwrap();
+        match handle.join().await {
+            Err(Error::Decode(e)) => assert_eq!(e.offset(), Some(6)),
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn spoofed_command() {
+        let (handle, script) = MockPeer::default()
+            .send_raw(&b"\x00\x08_command\x00\x03Sum\x00\x08_command\x00\x04Drop\x00\x00"[..])
+            .serve(Builder::default());
+
+        script.await.unwrap().unwrap();
+        match handle.join().await {
+            Err(Error::Decode(DecodeError::DuplicateKey { key, offset: 15 })) => {
+                assert_eq!(key, "_command")
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn unexpected_command() {
+        let (handle, script) = MockPeer::default()
+            .expect("Sum", &[])
+            .serve(Builder::default());
+
+        let mut sender = handle.request_sender().unwrap();
+        sender
+            .call_remote_noreply("Product".into(), Sum { a: 1, b: 2 })
+            .await
+            .unwrap();
+
+        match script.await.unwrap() {
+            Err(ScriptError::Unexpected { expected, got }) => {
+                assert_eq!(expected, "Sum");
+                assert_eq!(got.len(), 3);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,309 @@
+//! Adapters from byte streams and message transports to the streams of
+//! whole boxes the connection runs on.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{future, ready, sink, stream, Sink, StreamExt};
+use tokio::io::{AsyncWrite, ReadBuf};
+use tokio_util::codec::Decoder as _;
+
+use crate::{AmpVersion, Builder, Decoder, Dispatcher, Error, Handle, RawFrame, Runtime};
+
+/// The largest datagram we expect to receive.
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+/// Writes each encoded box to a byte stream straight from its buffer.
+pub(crate) struct ByteSink<W> {
+    inner: W,
+    pending: Bytes,
+}
+
+impl<W: AsyncWrite + Unpin> ByteSink<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        ByteSink {
+            inner,
+            pending: Bytes::new(),
+        }
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            let count = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending))?;
+            if count == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pending.advance(count);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Sink<Bytes> for ByteSink<W> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.get_mut().poll_drain(cx).map_err(Into::into)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Error> {
+        debug_assert!(self.pending.is_empty());
+        self.get_mut().pending = item;
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx).map_err(Into::into)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner)
+            .poll_shutdown(cx)
+            .map_err(Into::into)
+    }
+}
+
+/// A connected socket that keeps message boundaries.
+pub trait Datagrams: Send + Sync + 'static {
+    fn poll_recv(&self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>>;
+
+    fn poll_send(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>>;
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl Datagrams for tokio::net::UdpSocket {
+    fn poll_recv(&self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        tokio::net::UdpSocket::poll_recv(self, cx, buf)
+    }
+
+    fn poll_send(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        tokio::net::UdpSocket::poll_send(self, cx, buf)
+    }
+}
+
+#[cfg(all(unix, feature = "runtime-tokio"))]
+impl Datagrams for tokio::net::UnixDatagram {
+    fn poll_recv(&self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        tokio::net::UnixDatagram::poll_recv(self, cx, buf)
+    }
+
+    fn poll_send(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        tokio::net::UnixDatagram::poll_send(self, cx, buf)
+    }
+}
+
+impl<D: Dispatcher, V, R: Runtime> Builder<D, V, R>
+where
+    V: AmpVersion + Send + 'static,
+{
+    /// Serve over a connected datagram socket, one box per datagram.
+    ///
+    /// A box must fit in one datagram: sending a larger one, as V2 allows,
+    /// fails and ends the connection.
+    pub fn serve_datagrams<S: Datagrams>(self, socket: S) -> Handle<V> {
+        let socket = Arc::new(socket);
+
+        let input = stream::unfold(
+            (socket.clone(), vec![0; MAX_DATAGRAM]),
+            |(socket, mut buf)| async move {
+                let message = future::poll_fn(|cx| {
+                    let mut read = ReadBuf::new(&mut buf);
+                    ready!(socket.poll_recv(cx, &mut read))?;
+                    Poll::Ready(Ok(BytesMut::from(read.filled())))
+                })
+                .await;
+                Some((message, (socket, buf)))
+            },
+        );
+
+        let output = sink::unfold(socket, |socket, message: Bytes| async move {
+            let count = future::poll_fn(|cx| socket.poll_send(cx, &message)).await?;
+            if count < message.len() {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            Ok(socket)
+        });
+
+        self.serve_messages(input.boxed(), Box::pin(output))
+    }
+}
+
+/// Decode the boxes in one message, none of which may continue into the
+/// next message.
+pub(crate) fn decode_message<V: AmpVersion>(
+    decoder: &mut Decoder<V, RawFrame>,
+    mut message: BytesMut,
+) -> Result<Vec<RawFrame>, Error> {
+    let mut frames = Vec::new();
+
+    while let Some(frame) = decoder.decode_eof(&mut message)? {
+        frames.push(frame);
+    }
+
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod test {
+    use futures::SinkExt;
+    #[cfg(feature = "runtime-tokio")]
+    use tokio::net::UdpSocket;
+
+    use super::*;
+    #[cfg(feature = "runtime-tokio")]
+    use crate::testing::Echo;
+    use crate::{DecodeError, V1};
+
+    #[test]
+    fn several_boxes_per_message() {
+        let mut decoder = Decoder::<V1, RawFrame>::new();
+        let message = BytesMut::from(&b"\x00\x01a\x00\x011\x00\x00\x00\x01b\x00\x012\x00\x00"[..]);
+
+        let frames = decode_message(&mut decoder, message).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1][&b"b"[..]], "2");
+    }
+
+    #[test]
+    fn box_spanning_messages() {
+        let mut decoder = Decoder::<V1, RawFrame>::new();
+        let message = BytesMut::from(&b"\x00\x01a\x00\x011"[..]);
+
+        match decode_message(&mut decoder, message) {
+            Err(Error::Decode(DecodeError::TruncatedBox { offset: 6 })) => (),
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn byte_sink() {
+        let mut sink = ByteSink::new(Vec::new());
+
+        futures::executor::block_on(async {
+            sink.send(Bytes::from_static(b"hello ")).await.unwrap();
+            sink.send(Bytes::from_static(b"world")).await.unwrap();
+            sink.close().await.unwrap();
+        });
+        assert_eq!(sink.inner, b"hello world");
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    #[tokio::test]
+    async fn peer_gone_on_close() {
+        /// Accepts writes, then fails to shut down like a reset socket.
+        struct Reset;
+
+        impl AsyncWrite for Reset {
+            fn poll_write(
+                self: Pin<&mut Self>,
+                _cx: &mut Context<'_>,
+                data: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                Poll::Ready(Ok(data.len()))
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Poll::Ready(Err(io::ErrorKind::NotConnected.into()))
+            }
+        }
+
+        let mut handle = crate::Builder::default().serve(tokio::io::empty(), Reset);
+        handle.shutdown();
+        handle.join().await.unwrap();
+    }
+
+    #[cfg(all(unix, feature = "runtime-tokio"))]
+    #[tokio::test]
+    async fn unix_datagrams() {
+        use tokio::net::UnixDatagram;
+
+        let (left, right) = UnixDatagram::pair().unwrap();
+        let mut right = Builder::default()
+            .version2()
+            .dispatcher(Echo)
+            .serve_datagrams(right);
+        let mut left = Builder::default().version2().serve_datagrams(left);
+
+        let mut request = RawFrame::new();
+        request.insert("a".into(), "1".into());
+        let mut sender = left.request_sender().unwrap();
+        let reply: RawFrame = sender
+            .call_remote("Echo".into(), request.clone())
+            .await
+            .unwrap();
+        assert_eq!(reply, request);
+
+        let metrics = left.metrics();
+        assert_eq!(metrics.bytes_written, 33);
+        assert_eq!(metrics.bytes_read, 20);
+
+        drop(sender);
+        left.shutdown();
+        right.shutdown();
+        left.join().await.unwrap();
+        right.join().await.unwrap();
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    async fn udp_pair() -> (UdpSocket, UdpSocket) {
+        let left = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let right = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        left.connect(right.local_addr().unwrap()).await.unwrap();
+        right.connect(left.local_addr().unwrap()).await.unwrap();
+
+        (left, right)
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    #[tokio::test]
+    async fn udp_loopback() {
+        let (left, right) = udp_pair().await;
+        let mut right = Builder::default().dispatcher(Echo).serve_datagrams(right);
+        let mut left = Builder::default().serve_datagrams(left);
+
+        let mut sender = left.request_sender().unwrap();
+        for i in 0..10 {
+            let mut request = RawFrame::new();
+            request.insert("i".into(), i.to_string().into());
+            let reply: RawFrame = sender
+                .call_remote("Echo".into(), request.clone())
+                .await
+                .unwrap();
+            assert_eq!(reply, request);
+        }
+
+        drop(sender);
+        left.shutdown();
+        right.shutdown();
+        left.join().await.unwrap();
+        right.join().await.unwrap();
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    #[tokio::test]
+    async fn udp_oversized_box() {
+        let (left, _right) = udp_pair().await;
+        let left = Builder::default().version2().serve_datagrams(left);
+
+        let mut request = RawFrame::new();
+        request.insert("blob".into(), vec![b'x'; 70_000].into());
+        let mut sender = left.request_sender().unwrap();
+        let res: Result<RawFrame, _> = sender.call_remote("Echo".into(), request).await;
+        assert!(res.is_err());
+
+        drop(sender);
+        assert!(matches!(left.join().await, Err(Error::IO(_))));
+    }
+}
@@ -0,0 +1,141 @@
+//! Serve AMP over a WebSocket, one box per binary message.
+
+use std::io;
+
+use bytes::{Bytes, BytesMut};
+use futures::{future, Sink, SinkExt, Stream, StreamExt};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+use crate::{AmpVersion, Builder, Dispatcher, Handle, Runtime};
+
+impl<D: Dispatcher, V, R: Runtime> Builder<D, V, R>
+where
+    V: AmpVersion + Send + 'static,
+{
+    /// Serve over a WebSocket, such as a `tokio_tungstenite::WebSocketStream`.
+    pub fn serve_websocket<S>(self, stream: S) -> Handle<V>
+    where
+        S: Stream<Item = Result<Message, WsError>> + Sink<Message, Error = WsError>,
+        S: Unpin + Send + 'static,
+    {
+        let (output, input) = stream.split();
+
+        let input = input
+            .take_while(|message| {
+                future::ready(!matches!(
+                    message,
+                    Ok(Message::Close(_)) | Err(WsError::ConnectionClosed)
+                ))
+            })
+            .filter_map(|message| {
+                future::ready(match message {
+                    Ok(Message::Binary(data)) => Some(Ok(BytesMut::from(&data[..]))),
+                    Ok(Message::Text(_)) => Some(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "text message on an AMP WebSocket",
+                    ))),
+                    Ok(_) => None,
+                    Err(e) => Some(Err(ws_error(e))),
+                })
+            });
+        let output = output
+            .sink_map_err(ws_error)
+            .with(|message: Bytes| future::ready(Ok(Message::Binary(message.to_vec()))));
+
+        self.serve_messages(Box::pin(input), output)
+    }
+}
+
+fn ws_error(error: WsError) -> io::Error {
+    match error {
+        WsError::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::{accept_async, client_async};
+
+    use super::*;
+    use crate::testing::Echo;
+    use crate::RawFrame;
+
+    async fn echo_server() -> (String, tokio::task::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let ws = accept_async(tcp).await.unwrap();
+            let handle = Builder::default()
+                .version2()
+                .dispatcher(Echo)
+                .serve_websocket(ws);
+            handle.join().await.unwrap();
+        });
+
+        (url, server)
+    }
+
+    async fn connect(url: &str) -> tokio_tungstenite::WebSocketStream<TcpStream> {
+        let addr = url.trim_start_matches("ws://").trim_end_matches('/');
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        client_async(url, tcp).await.unwrap().0
+    }
+
+    #[tokio::test]
+    async fn loopback() {
+        let (url, server) = echo_server().await;
+        let mut client = Builder::default()
+            .version2()
+            .serve_websocket(connect(&url).await);
+
+        let mut request = RawFrame::new();
+        request.insert("blob".into(), vec![b'x'; 70_000].into());
+
+        let mut sender = client.request_sender().unwrap();
+        let reply: RawFrame = sender
+            .call_remote("Echo".into(), request.clone())
+            .await
+            .unwrap();
+        assert_eq!(reply, request);
+
+        drop(sender);
+        client.shutdown();
+        client.join().await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn one_box_per_message() {
+        let (url, server) = echo_server().await;
+        let mut ws = connect(&url).await;
+
+        let first = b"\x00\x04_ask\x00\x01a\x00\x08_command\x00\x04Echo\x00\x00".to_vec();
+        let second = b"\x00\x04_ask\x00\x01b\x00\x08_command\x00\x04Echo\x00\x00".to_vec();
+        let both = [first, second].concat();
+        ws.send(Message::Binary(both)).await.unwrap();
+
+        // Requests are dispatched concurrently, so replies may be reordered.
+        let mut replies = Vec::new();
+        for _ in 0..2 {
+            match ws.next().await.unwrap().unwrap() {
+                Message::Binary(data) => replies.push(data),
+                m => panic!("unexpected message {:?}", m),
+            }
+        }
+        replies.sort();
+        assert_eq!(
+            replies,
+            vec![
+                b"\x00\x07_answer\x00\x01a\x00\x00".to_vec(),
+                b"\x00\x07_answer\x00\x01b\x00\x00".to_vec(),
+            ]
+        );
+
+        ws.close(None).await.unwrap();
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+//! Integers of any length, as Twisted's `amp.Integer` sends them, with
+//! `#[serde(with = "amp_serde::bigint")]` on `BigInt` or `BigUint` fields.
+
+use std::fmt::{self, Display};
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use serde::de::{Error, Visitor};
+use serde::{Deserializer, Serializer};
+
+pub use num_bigint::{BigInt, BigUint};
+
+pub fn serialize<T: Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+pub fn deserialize<'de, T: FromStr, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+    deserializer.deserialize_str(DecimalVisitor(PhantomData))
+}
+
+struct DecimalVisitor<T>(PhantomData<T>);
+
+impl<T: FromStr> Visitor<'_> for DecimalVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a decimal integer")
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<T, E> {
+        // `FromStr` for big integers also takes `+` and `_`, which
+        // Twisted never sends.
+        let digits = v.strip_prefix('-').unwrap_or(v);
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(E::custom(crate::Error::ExpectedInteger));
+        }
+
+        v.parse()
+            .map_err(|_| E::custom(crate::Error::ExpectedInteger))
+    }
+
+    fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<T, E> {
+        let v = std::str::from_utf8(v).map_err(|_| E::custom(crate::Error::ExpectedInteger))?;
+        self.visit_str(v)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{from_bytes, to_bytes, V1};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Balance {
+        #[serde(with = "crate::bigint")]
+        total: BigInt,
+        #[serde(with = "crate::bigint")]
+        count: BigUint,
+    }
+
+    #[test]
+    fn round_trip() {
+        let total = "-123456789012345678901234567890123456789012345678901234567890";
+        let value = Balance {
+            total: total.parse().unwrap(),
+            count: BigUint::from(7u8),
+        };
+
+        let bytes = to_bytes::<V1, _>(&value).unwrap();
+        let expected = [
+            b"\x00\x05total\x00\x3d".as_ref(),
+            total.as_bytes(),
+            b"\x00\x05count\x00\x017\x00\x00",
+        ]
+        .concat();
+        assert_eq!(bytes, expected);
+        assert_eq!(from_bytes::<V1, _, Balance>(bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn only_digits() {
+        for total in ["+1", "1_000", " 1", "", "-"] {
+            let bytes = [
+                b"\x00\x05total".as_ref(),
+                &(total.len() as u16).to_be_bytes(),
+                total.as_bytes(),
+                b"\x00\x05count\x00\x011\x00\x00",
+            ]
+            .concat();
+
+            let error = from_bytes::<V1, _, Balance>(bytes).unwrap_err();
+            assert_eq!(error.path(), Some("total"), "{:?}", total);
+        }
+    }
+}
@@ -1,75 +1,271 @@
-use std::marker::PhantomData;
+use std::cell::{Cell, RefCell};
+use std::ops::Deref;
 use std::str::FromStr;
 
-use bytes::{Buf, Bytes};
+use bytes::Bytes;
 use serde::{
-    de::{DeserializeSeed, MapAccess, SeqAccess, Visitor},
+    de::{DeserializeSeed, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor},
     Deserialize,
 };
 
-use crate::{Error, Result, AMP_LENGTH_SIZE, AMP_VALUE_LIMIT, V1, V2};
+use crate::{
+    Error, Result, Step, Version, AMP_BYTES_COOKIE, AMP_EMPTY_NONE_COOKIE, AMP_LENGTH_SIZE,
+    AMP_VALUE_COOKIE, AMP_VALUE_KEY, AMP_VALUE_LIMIT, AMP_VARIANT_KEY, V1, V2,
+};
+
+thread_local! {
+    /// Passes a value to `zero_copy::deserialize` without copying it.
+    pub(crate) static SHARED_BYTES: RefCell<Option<Bytes>> = const { RefCell::new(None) };
+    /// Tells `AmpValue` which version its input was read with.
+    pub(crate) static SHARED_VERSION: Cell<Option<Version>> = const { Cell::new(None) };
+}
 
-struct AmpListHandler<'a, V>(&'a mut Deserializer<V>);
+struct AmpListHandler<'a, V, I> {
+    de: &'a mut Deserializer<V, I>,
+    index: usize,
+}
 
-pub struct Deserializer<V> {
-    input: Bytes,
-    marker: PhantomData<V>,
+pub struct Deserializer<V, I = Bytes> {
+    input: I,
+    version: V,
+    /// The last key read from a box, for error paths.
+    key: I,
+    /// The next item's position in a list, for error paths.
+    index: usize,
 }
 
-pub trait AmpDecoder {
-    fn read_map_value(input: &mut Bytes) -> Result<Bytes>;
+pub trait AmpDecoder: Copy {
+    /// Whether a value may continue over several segments.
+    fn chunked(self) -> bool;
 }
 
 impl AmpDecoder for V1 {
-    fn read_map_value(input: &mut Bytes) -> Result<Bytes> {
-        if input.len() < AMP_LENGTH_SIZE {
-            return Err(Error::ExpectedMapValue);
+    fn chunked(self) -> bool {
+        false
+    }
+}
+
+impl AmpDecoder for V2 {
+    fn chunked(self) -> bool {
+        true
+    }
+}
+
+impl AmpDecoder for Version {
+    fn chunked(self) -> bool {
+        self == Version::V2
+    }
+}
+
+/// What a `Deserializer` reads from: shared `Bytes`, or a slice that
+/// borrowed values point into.
+pub trait Input<'de>: Clone + Default + Deref<Target = [u8]> {
+    fn split_to(&mut self, at: usize) -> Self;
+
+    fn visit_bytes<T: Visitor<'de>>(self, visitor: T) -> Result<T::Value>;
+
+    fn visit_str<T: Visitor<'de>>(self, visitor: T) -> Result<T::Value>;
+
+    /// The input as `Bytes`, if that needs no copy.
+    fn shared(&mut self) -> Option<Bytes>;
+
+    fn skip(&mut self, count: usize) {
+        self.split_to(count);
+    }
+
+    fn take_u16(&mut self) -> u16 {
+        let length = self.split_to(AMP_LENGTH_SIZE);
+        u16::from_be_bytes([length[0], length[1]])
+    }
+}
+
+impl<'de> Input<'de> for Bytes {
+    fn split_to(&mut self, at: usize) -> Self {
+        Bytes::split_to(self, at)
+    }
+
+    fn visit_bytes<T: Visitor<'de>>(self, visitor: T) -> Result<T::Value> {
+        visitor.visit_bytes(&self)
+    }
+
+    fn visit_str<T: Visitor<'de>>(self, visitor: T) -> Result<T::Value> {
+        visitor.visit_str(std::str::from_utf8(&self).map_err(|_| Error::ExpectedUtf8)?)
+    }
+
+    fn shared(&mut self) -> Option<Bytes> {
+        Some(std::mem::take(self))
+    }
+}
+
+impl<'de> Input<'de> for &'de [u8] {
+    fn split_to(&mut self, at: usize) -> Self {
+        let (head, tail) = self.split_at(at);
+        *self = tail;
+        head
+    }
+
+    fn visit_bytes<T: Visitor<'de>>(self, visitor: T) -> Result<T::Value> {
+        visitor.visit_borrowed_bytes(self)
+    }
+
+    fn visit_str<T: Visitor<'de>>(self, visitor: T) -> Result<T::Value> {
+        visitor.visit_borrowed_str(std::str::from_utf8(self).map_err(|_| Error::ExpectedUtf8)?)
+    }
+
+    fn shared(&mut self) -> Option<Bytes> {
+        None
+    }
+}
+
+/// A map value, which is only copied if it spans several segments.
+#[derive(Clone)]
+pub(crate) enum Value<I> {
+    Input(I),
+    Joined(Bytes),
+}
+
+impl<I> Value<I> {
+    fn deserialize<'de, V, T>(self, version: V, seed: T) -> Result<T::Value>
+    where
+        I: Input<'de>,
+        V: AmpDecoder,
+        T: DeserializeSeed<'de>,
+    {
+        match self {
+            Value::Input(input) => deserialize_all(input, version, seed),
+            Value::Joined(bytes) => deserialize_all(bytes, version, seed),
         }
-        let length: usize = input.get_u16().into();
+    }
+
+    /// Deserialize the value of `key`, naming it in any error.
+    fn deserialize_at<'de, V, T>(self, key: &[u8], version: V, seed: T) -> Result<T::Value>
+    where
+        I: Input<'de>,
+        V: AmpDecoder,
+        T: DeserializeSeed<'de>,
+    {
+        let raw = self.clone();
+        self.deserialize(version, seed)
+            .map_err(|e| e.within(Step::Key(key), Some(raw.as_slice())))
+    }
 
-        if input.len() < length {
-            return Err(Error::ExpectedMapValue);
+    fn as_slice(&self) -> &[u8]
+    where
+        I: Deref<Target = [u8]>,
+    {
+        match self {
+            Value::Input(input) => input,
+            Value::Joined(bytes) => bytes,
         }
+    }
+}
 
-        Ok(input.split_to(length))
+impl Value<Bytes> {
+    pub(crate) fn into_bytes(self) -> Bytes {
+        match self {
+            Value::Input(bytes) | Value::Joined(bytes) => bytes,
+        }
     }
 }
-impl AmpDecoder for V2 {
-    fn read_map_value(input: &mut Bytes) -> Result<Bytes> {
-        let mut done = false;
-        let mut value = Vec::new();
-
-        while !done {
-            let segment = V1::read_map_value(&mut *input)?;
-            value.extend_from_slice(&segment);
-            done = segment.len() != AMP_VALUE_LIMIT;
+
+pub(crate) fn read_segment<'de, I: Input<'de>>(input: &mut I) -> Result<I> {
+    if input.len() < AMP_LENGTH_SIZE {
+        return Err(Error::ExpectedMapValue);
+    }
+    let length: usize = input.take_u16().into();
+
+    if input.len() < length {
+        return Err(Error::ExpectedMapValue);
+    }
+
+    Ok(input.split_to(length))
+}
+
+pub(crate) fn read_value<'de, V: AmpDecoder, I: Input<'de>>(
+    version: V,
+    input: &mut I,
+) -> Result<Value<I>> {
+    let first = read_segment(&mut *input)?;
+    if !version.chunked() || first.len() != AMP_VALUE_LIMIT {
+        return Ok(Value::Input(first));
+    }
+
+    // Size the joined value from the segment lengths up front.
+    let mut total = first.len();
+    let mut rest: &[u8] = input;
+    while let [high, low, tail @ ..] = rest {
+        let length = usize::from(u16::from_be_bytes([*high, *low]));
+        total += length;
+        rest = tail.get(length..).unwrap_or_default();
+        if length != AMP_VALUE_LIMIT {
+            break;
         }
+    }
 
-        Ok(value.into())
+    let mut value = Vec::with_capacity(total);
+    value.extend_from_slice(&first);
+    loop {
+        let segment = read_segment(&mut *input)?;
+        value.extend_from_slice(&segment);
+        if segment.len() != AMP_VALUE_LIMIT {
+            return Ok(Value::Joined(value.into()));
+        }
     }
 }
 
-impl<'de, V> Deserializer<V> {
+fn deserialize_all<'de, V, I, T>(input: I, version: V, seed: T) -> Result<T::Value>
+where
+    V: AmpDecoder,
+    I: Input<'de>,
+    T: DeserializeSeed<'de>,
+{
+    let mut sub = Deserializer::with_version(input, version);
+    let res = seed.deserialize(&mut sub)?;
+
+    if sub.input.is_empty() {
+        Ok(res)
+    } else {
+        Err(Error::RemainingBytes)
+    }
+}
+
+impl<V: Default> Deserializer<V> {
     pub fn from_bytes(input: Bytes) -> Self {
+        Self::with_version(input, V::default())
+    }
+}
+
+impl<'de, V: Default> Deserializer<V, &'de [u8]> {
+    /// Deserialize borrowing from `input`.
+    pub fn from_slice(input: &'de [u8]) -> Self {
+        Self::with_version(input, V::default())
+    }
+}
+
+impl<V, I: Default + Deref<Target = [u8]>> Deserializer<V, I> {
+    pub fn with_version(input: I, version: V) -> Self {
         Deserializer {
             input,
-            marker: PhantomData,
+            version,
+            key: I::default(),
+            index: 0,
         }
     }
 
-    fn parse_int<I: FromStr>(&mut self) -> Result<I> {
+    fn take_input(&mut self) -> I {
+        std::mem::take(&mut self.input)
+    }
+
+    fn parse_int<N: FromStr>(&mut self) -> Result<N> {
         std::str::from_utf8(&self.input)
             .ok()
             .and_then(|v| v.parse().ok())
             .ok_or(Error::ExpectedInteger)
-            .map(|v| {
-                self.input.clear();
-                v
-            })
+            .inspect(|_| self.input = I::default())
     }
 }
 
-impl<'de, 'a, V: AmpDecoder> serde::Deserializer<'de> for &'a mut Deserializer<V> {
+impl<'de, V: AmpDecoder, I: Input<'de>> serde::Deserializer<'de> for &mut Deserializer<V, I> {
     type Error = Error;
 
     fn deserialize_any<T>(self, visitor: T) -> Result<T::Value>
@@ -91,18 +287,54 @@ impl<'de, 'a, V: AmpDecoder> serde::Deserializer<'de> for &'a mut Deserializer<V
         self,
         _name: &'static str,
         _variants: &'static [&'static str],
-        _visitor: T,
+        visitor: T,
     ) -> Result<T::Value>
     where
         T: Visitor<'de>,
     {
-        Err(Error::Unsupported)
+        let input = self.take_input();
+
+        // A box starts with the length of a short key, where a variant
+        // name cannot start with a NUL.
+        if input.first() == Some(&0) {
+            visitor.visit_enum(Variant::from_box(input, self.version)?)
+        } else {
+            visitor.visit_enum(Variant {
+                name: Value::Input(input),
+                fields: Vec::new(),
+                version: self.version,
+            })
+        }
     }
 
-    fn deserialize_newtype_struct<T>(self, _name: &'static str, visitor: T) -> Result<T::Value>
+    fn deserialize_newtype_struct<T>(self, name: &'static str, visitor: T) -> Result<T::Value>
     where
         T: Visitor<'de>,
     {
+        if name == AMP_EMPTY_NONE_COOKIE {
+            return if self.input.is_empty() {
+                visitor.visit_none()
+            } else {
+                visitor.visit_some(self)
+            };
+        }
+
+        if name == AMP_VALUE_COOKIE {
+            let version = if self.version.chunked() {
+                Version::V2
+            } else {
+                Version::V1
+            };
+            SHARED_VERSION.with(|shared| shared.set(Some(version)));
+        }
+
+        if name == AMP_BYTES_COOKIE || name == AMP_VALUE_COOKIE {
+            if let Some(bytes) = self.input.shared() {
+                SHARED_BYTES.with(|shared| *shared.borrow_mut() = Some(bytes));
+                return visitor.visit_unit();
+            }
+        }
+
         visitor.visit_newtype_struct(self)
     }
 
@@ -118,10 +350,10 @@ impl<'de, 'a, V: AmpDecoder> serde::Deserializer<'de> for &'a mut Deserializer<V
         T: Visitor<'de>,
     {
         if self.input.eq_ignore_ascii_case(b"true") {
-            self.input.clear();
+            self.take_input();
             visitor.visit_bool(true)
         } else if self.input.eq_ignore_ascii_case(b"false") {
-            self.input.clear();
+            self.take_input();
             visitor.visit_bool(false)
         } else {
             Err(Error::ExpectedBool)
@@ -156,6 +388,13 @@ impl<'de, 'a, V: AmpDecoder> serde::Deserializer<'de> for &'a mut Deserializer<V
         visitor.visit_i64(self.parse_int()?)
     }
 
+    fn deserialize_i128<T>(self, visitor: T) -> Result<T::Value>
+    where
+        T: Visitor<'de>,
+    {
+        visitor.visit_i128(self.parse_int()?)
+    }
+
     fn deserialize_u8<T>(self, visitor: T) -> Result<T::Value>
     where
         T: Visitor<'de>,
@@ -184,6 +423,13 @@ impl<'de, 'a, V: AmpDecoder> serde::Deserializer<'de> for &'a mut Deserializer<V
         visitor.visit_u64(self.parse_int()?)
     }
 
+    fn deserialize_u128<T>(self, visitor: T) -> Result<T::Value>
+    where
+        T: Visitor<'de>,
+    {
+        visitor.visit_u128(self.parse_int()?)
+    }
+
     fn deserialize_f32<T>(self, visitor: T) -> Result<T::Value>
     where
         T: Visitor<'de>,
@@ -210,7 +456,7 @@ impl<'de, 'a, V: AmpDecoder> serde::Deserializer<'de> for &'a mut Deserializer<V
             )
         }?;
 
-        self.input.clear();
+        self.take_input();
         Ok(res)
     }
 
@@ -218,12 +464,7 @@ impl<'de, 'a, V: AmpDecoder> serde::Deserializer<'de> for &'a mut Deserializer<V
     where
         T: Visitor<'de>,
     {
-        visitor
-            .visit_str(std::str::from_utf8(&self.input).map_err(|_| Error::ExpectedUtf8)?)
-            .map(|v| {
-                self.input.clear();
-                v
-            })
+        self.take_input().visit_str(visitor)
     }
 
     fn deserialize_string<T>(self, visitor: T) -> Result<T::Value>
@@ -237,10 +478,7 @@ impl<'de, 'a, V: AmpDecoder> serde::Deserializer<'de> for &'a mut Deserializer<V
     where
         T: Visitor<'de>,
     {
-        visitor.visit_bytes(&self.input).map(|v| {
-            self.input.clear();
-            v
-        })
+        self.take_input().visit_bytes(visitor)
     }
 
     fn deserialize_byte_buf<T>(self, visitor: T) -> Result<T::Value>
@@ -261,11 +499,8 @@ impl<'de, 'a, V: AmpDecoder> serde::Deserializer<'de> for &'a mut Deserializer<V
     where
         T: Visitor<'de>,
     {
-        if self.input.is_empty() {
-            visitor.visit_none()
-        } else {
-            visitor.visit_some(self)
-        }
+        // Absent fields are `None`; see `empty_none` for empty values.
+        visitor.visit_some(self)
     }
 
     fn deserialize_seq<T>(self, visitor: T) -> Result<T::Value>
@@ -293,7 +528,7 @@ impl<'de, 'a, V: AmpDecoder> serde::Deserializer<'de> for &'a mut Deserializer<V
     {
         // Ugly hack for AmpList
         if name == crate::AMP_LIST_COOKIE {
-            visitor.visit_seq(AmpListHandler(self))
+            visitor.visit_seq(AmpListHandler { de: self, index: 0 })
         } else {
             visitor.visit_seq(self)
         }
@@ -331,10 +566,7 @@ impl<'de, 'a, V: AmpDecoder> serde::Deserializer<'de> for &'a mut Deserializer<V
         };
 
         if i.next().is_none() {
-            visitor.visit_char(c).map(|v| {
-                self.input.clear();
-                v
-            })
+            visitor.visit_char(c).inspect(|_| self.input = I::default())
         } else {
             Err(Error::ExpectedChar)
         }
@@ -348,7 +580,7 @@ impl<'de, 'a, V: AmpDecoder> serde::Deserializer<'de> for &'a mut Deserializer<V
     }
 }
 
-impl<'de, 'a, V: AmpDecoder> SeqAccess<'de> for &'a mut Deserializer<V> {
+impl<'de, V: AmpDecoder, I: Input<'de>> SeqAccess<'de> for &mut Deserializer<V, I> {
     type Error = Error;
 
     fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
@@ -358,24 +590,28 @@ impl<'de, 'a, V: AmpDecoder> SeqAccess<'de> for &'a mut Deserializer<V> {
         if self.input.len() < AMP_LENGTH_SIZE {
             return Err(Error::ExpectedSeqLength);
         }
-        let length: usize = self.input.get_u16().into();
+        let length: usize = self.input.take_u16().into();
 
         if self.input.is_empty() {
             Ok(None)
         } else if self.input.len() >= length {
-            let mut sub = Deserializer::<V>::from_bytes(self.input.split_to(length));
-            let res = seed.deserialize(&mut sub).map(Some);
+            let element = self.input.split_to(length);
+            let step = Step::Index(self.index);
+            self.index += 1;
+
+            let mut sub = Deserializer::with_version(element.clone(), self.version);
+            let mut res = seed.deserialize(&mut sub).map(Some);
             if !sub.input.is_empty() {
-                return Err(Error::RemainingBytes);
+                res = Err(Error::RemainingBytes);
             }
-            res
+            res.map_err(|e| e.within(step, Some(&element)))
         } else {
             Err(Error::ExpectedSeqValue)
         }
     }
 }
 
-impl<'de, 'a, V: AmpDecoder> MapAccess<'de> for &'a mut Deserializer<V> {
+impl<'de, V: AmpDecoder, I: Input<'de>> MapAccess<'de> for &mut Deserializer<V, I> {
     type Error = Error;
 
     fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
@@ -383,20 +619,21 @@ impl<'de, 'a, V: AmpDecoder> MapAccess<'de> for &'a mut Deserializer<V> {
         K: DeserializeSeed<'de>,
     {
         if self.input.starts_with(&[0, 0]) {
-            self.input.advance(AMP_LENGTH_SIZE);
+            self.input.skip(AMP_LENGTH_SIZE);
             return Ok(None);
         } else if self.input.len() < AMP_LENGTH_SIZE {
             return Err(Error::ExpectedMapKey);
         }
 
-        let length: usize = self.input.get_u16().into();
+        let length: usize = self.input.take_u16().into();
 
         if length > crate::AMP_KEY_LIMIT {
             return Err(Error::ExpectedMapKey);
         }
 
         if self.input.len() >= length {
-            let mut sub = Deserializer::<V>::from_bytes(self.input.split_to(length));
+            self.key = self.input.split_to(length);
+            let mut sub = Deserializer::with_version(self.key.clone(), self.version);
             let res = seed.deserialize(&mut sub).map(Some);
             if !sub.input.is_empty() {
                 return Err(Error::RemainingBytes);
@@ -411,42 +648,276 @@ impl<'de, 'a, V: AmpDecoder> MapAccess<'de> for &'a mut Deserializer<V> {
     where
         T: DeserializeSeed<'de>,
     {
-        let value = V::read_map_value(&mut self.input)?;
-        let mut sub = Deserializer::<V>::from_bytes(value);
-        let res = seed.deserialize(&mut sub)?;
+        let key = std::mem::take(&mut self.key);
+        read_value(self.version, &mut self.input)?.deserialize_at(&key, self.version, seed)
+    }
+}
 
-        if sub.input.is_empty() {
-            Ok(res)
-        } else {
-            Err(Error::RemainingBytes)
+impl<'de, V: AmpDecoder, I: Input<'de>> SeqAccess<'de> for AmpListHandler<'_, V, I> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.de.input.is_empty() {
+            return Ok(None);
         }
+
+        let step = Step::Index(self.index);
+        self.index += 1;
+        let raw = self.de.input.clone();
+        seed.deserialize(&mut *self.de)
+            .map(Some)
+            .map_err(|e| e.within(step, Some(&raw)))
     }
 }
 
-impl<'de, 'a, V: AmpDecoder> SeqAccess<'de> for AmpListHandler<'a, V> {
+/// An enum variant, with the other entries of its box if it was tagged.
+struct Variant<V, I> {
+    name: Value<I>,
+    fields: Vec<(I, Value<I>)>,
+    version: V,
+}
+
+impl<'de, V: AmpDecoder, I: Input<'de>> Variant<V, I> {
+    fn from_box(mut input: I, version: V) -> Result<Self> {
+        let mut name = None;
+        let mut fields = Vec::new();
+
+        loop {
+            let key = read_segment(&mut input).map_err(|_| Error::ExpectedMapKey)?;
+            if key.is_empty() {
+                break;
+            }
+
+            let value = read_value(version, &mut input)?;
+            if *key == *AMP_VARIANT_KEY.as_bytes() {
+                name = Some(value);
+            } else {
+                fields.push((key, value));
+            }
+        }
+
+        if !input.is_empty() {
+            return Err(Error::RemainingBytes);
+        }
+
+        Ok(Variant {
+            name: name.ok_or(Error::ExpectedVariant)?,
+            fields,
+            version,
+        })
+    }
+}
+
+impl<'de, V: AmpDecoder, I: Input<'de>> EnumAccess<'de> for Variant<V, I> {
     type Error = Error;
+    type Variant = VariantFields<V, I>;
 
-    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    fn variant_seed<T>(self, seed: T) -> Result<(T::Value, Self::Variant)>
     where
         T: DeserializeSeed<'de>,
     {
-        if self.0.input.is_empty() {
-            Ok(None)
+        let name = self.name.deserialize(self.version, seed)?;
+        let fields = VariantFields {
+            fields: self.fields.into_iter(),
+            value: None,
+            version: self.version,
+        };
+
+        Ok((name, fields))
+    }
+}
+
+struct VariantFields<V, I> {
+    fields: std::vec::IntoIter<(I, Value<I>)>,
+    value: Option<(I, Value<I>)>,
+    version: V,
+}
+
+impl<'de, V: AmpDecoder, I: Input<'de>> VariantAccess<'de> for VariantFields<V, I> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        if self.fields.len() == 0 {
+            Ok(())
         } else {
-            seed.deserialize(&mut *self.0).map(Some)
+            Err(Error::RemainingBytes)
         }
     }
+
+    fn newtype_variant_seed<T>(mut self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match (self.fields.next(), self.fields.len()) {
+            (Some((key, value)), 0) if *key == *AMP_VALUE_KEY.as_bytes() => {
+                value.deserialize_at(&key, self.version, seed)
+            }
+            _ => Err(Error::ExpectedVariant),
+        }
+    }
+
+    fn tuple_variant<T>(self, _len: usize, _visitor: T) -> Result<T::Value>
+    where
+        T: Visitor<'de>,
+    {
+        Err(Error::Unsupported)
+    }
+
+    fn struct_variant<T>(self, _fields: &'static [&'static str], visitor: T) -> Result<T::Value>
+    where
+        T: Visitor<'de>,
+    {
+        visitor.visit_map(self)
+    }
 }
 
-pub fn from_bytes<'a, V: AmpDecoder, B: Into<Bytes>, T>(s: B) -> Result<T>
+impl<'de, V: AmpDecoder, I: Input<'de>> MapAccess<'de> for VariantFields<V, I> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        match self.fields.next() {
+            Some((key, value)) => {
+                self.value = Some((key.clone(), value));
+                deserialize_all(key, self.version, seed).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<T>(&mut self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        let (key, value) = self.value.take().ok_or(Error::ExpectedMapValue)?;
+        value.deserialize_at(&key, self.version, seed)
+    }
+}
+
+pub fn from_bytes<'a, V: AmpDecoder + Default, B: Into<Bytes>, T>(s: B) -> Result<T>
 where
     T: Deserialize<'a>,
 {
-    let mut deserializer = Deserializer::<V>::from_bytes(s.into());
-    let t = T::deserialize(&mut deserializer)?;
-    if deserializer.input.is_empty() {
-        Ok(t)
-    } else {
-        Err(Error::RemainingBytes)
+    from_bytes_with(V::default(), s)
+}
+
+pub fn from_bytes_with<'a, V: AmpDecoder, B: Into<Bytes>, T>(version: V, s: B) -> Result<T>
+where
+    T: Deserialize<'a>,
+{
+    deserialize_all(s.into(), version, std::marker::PhantomData)
+}
+
+/// Deserialize borrowing from `input`, so that `&str`, `&[u8]` and
+/// borrowed `Cow` fields point into it.
+pub fn from_slice<'de, V: AmpDecoder + Default, T>(input: &'de [u8]) -> Result<T>
+where
+    T: Deserialize<'de>,
+{
+    from_slice_with(V::default(), input)
+}
+
+pub fn from_slice_with<'de, V: AmpDecoder, T>(version: V, input: &'de [u8]) -> Result<T>
+where
+    T: Deserialize<'de>,
+{
+    deserialize_all(input, version, std::marker::PhantomData)
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use serde::Deserialize;
+
+    use crate::{from_slice, to_bytes, AmpList, Error, V1, V2};
+
+    #[derive(Deserialize)]
+    struct Borrowed<'a> {
+        name: &'a str,
+        blob: &'a [u8],
+        #[serde(borrow)]
+        note: Cow<'a, str>,
+    }
+
+    fn within(input: &[u8], value: &[u8]) -> bool {
+        input.as_ptr_range().contains(&value.as_ptr())
+    }
+
+    #[test]
+    fn borrowed_fields() {
+        let input =
+            b"\x00\x04name\x00\x03amp\x00\x04blob\x00\x02\xff\x00\x00\x04note\x00\x02ok\x00\x00";
+        let value: Borrowed<'_> = from_slice::<V1, _>(input).unwrap();
+
+        assert_eq!(value.name, "amp");
+        assert_eq!(value.blob, b"\xff\x00");
+        assert!(within(input, value.name.as_bytes()));
+        assert!(within(input, value.blob));
+        assert!(matches!(value.note, Cow::Borrowed("ok")));
+    }
+
+    #[test]
+    fn joined_chunks() {
+        #[derive(serde::Serialize)]
+        struct Note<'a> {
+            note: &'a str,
+        }
+
+        #[derive(Deserialize)]
+        struct CowNote<'a> {
+            #[serde(borrow)]
+            note: Cow<'a, str>,
+        }
+
+        let long = "x".repeat(70_000);
+        let input = to_bytes::<V2, _>(Note { note: &long }).unwrap();
+
+        // A value spanning segments has to be copied.
+        let value: CowNote<'_> = from_slice::<V2, _>(&input).unwrap();
+        assert!(matches!(value.note, Cow::Owned(ref note) if *note == long));
+
+        let short = to_bytes::<V2, _>(Note { note: "x" }).unwrap();
+        let value: CowNote<'_> = from_slice::<V2, _>(&short).unwrap();
+        assert!(matches!(value.note, Cow::Borrowed("x")));
+    }
+
+    #[test]
+    fn error_paths() {
+        #[derive(Debug, Deserialize)]
+        struct Op {
+            #[allow(dead_code)]
+            b: u32,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Batch {
+            #[allow(dead_code)]
+            ops: AmpList<Op>,
+        }
+
+        let ops = [
+            b"\x00\x01b\x00\x011\x00\x00".as_ref(),
+            b"\x00\x01b\x00\x012\x00\x00",
+            b"\x00\x01b\x00\x03\"x\xff\x00\x00",
+        ]
+        .concat();
+        let mut input = b"\x00\x03ops".to_vec();
+        input.extend_from_slice(&(ops.len() as u16).to_be_bytes());
+        input.extend_from_slice(&ops);
+        input.extend_from_slice(b"\x00\x00");
+
+        let error = from_slice::<V1, Batch>(&input).unwrap_err();
+        assert!(matches!(error.kind(), Error::ExpectedInteger));
+        assert_eq!(error.path(), Some("ops[2].b"));
+        assert_eq!(
+            error.to_string(),
+            r#"Expected an integer at ops[2].b (value "\"x\xff")"#
+        );
     }
 }
@@ -0,0 +1,347 @@
+//! Twisted's `amp.Decimal`, which sends Python `decimal.Decimal` values
+//! as `str()` renders them.
+//!
+//! Values keep Python's sign, coefficient, exponent and NaN diagnostic
+//! digits, so they are sent back exactly as they came. `to_finite`
+//! converts those that fit to `rust_decimal::Decimal`.
+
+use std::convert::TryFrom;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use serde::de::Visitor;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::Error;
+
+/// A Python `decimal.Decimal`.
+///
+/// Equality compares representations, so `1.0` and `1.00` differ.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Decimal {
+    negative: bool,
+    kind: Kind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Kind {
+    /// `digits * 10^exponent`, the digits without leading zeros.
+    Finite {
+        digits: String,
+        exponent: i64,
+    },
+    Infinity,
+    /// A quiet NaN and its diagnostic digits.
+    NaN(String),
+    /// A signaling NaN and its diagnostic digits.
+    SNaN(String),
+}
+
+impl Decimal {
+    pub const INFINITY: Decimal = Decimal {
+        negative: false,
+        kind: Kind::Infinity,
+    };
+    pub const NEG_INFINITY: Decimal = Decimal {
+        negative: true,
+        kind: Kind::Infinity,
+    };
+    pub const NAN: Decimal = Decimal {
+        negative: false,
+        kind: Kind::NaN(String::new()),
+    };
+    pub const SNAN: Decimal = Decimal {
+        negative: false,
+        kind: Kind::SNaN(String::new()),
+    };
+
+    pub fn is_sign_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn is_finite(&self) -> bool {
+        matches!(self.kind, Kind::Finite { .. })
+    }
+
+    /// Whether this is a NaN, quiet or signaling.
+    pub fn is_nan(&self) -> bool {
+        matches!(self.kind, Kind::NaN(_) | Kind::SNaN(_))
+    }
+
+    /// The value as a `rust_decimal::Decimal`, if it is finite and fits.
+    ///
+    /// A positive exponent is multiplied out, so `1E+3` becomes `1000`.
+    pub fn to_finite(&self) -> Option<rust_decimal::Decimal> {
+        let (digits, exponent) = match &self.kind {
+            Kind::Finite { digits, exponent } => (digits, *exponent),
+            _ => return None,
+        };
+
+        let mut mantissa: i128 = digits.parse().ok()?;
+        let shift = u32::try_from(exponent.unsigned_abs()).ok()?;
+        let scale = if exponent > 0 {
+            mantissa = mantissa.checked_mul(10i128.checked_pow(shift)?)?;
+            0
+        } else {
+            shift
+        };
+
+        let mut value = rust_decimal::Decimal::try_from_i128_with_scale(mantissa, scale).ok()?;
+        value.set_sign_negative(self.negative);
+        Some(value)
+    }
+}
+
+impl From<rust_decimal::Decimal> for Decimal {
+    fn from(value: rust_decimal::Decimal) -> Self {
+        Decimal {
+            negative: value.is_sign_negative(),
+            kind: Kind::Finite {
+                digits: value.mantissa().unsigned_abs().to_string(),
+                exponent: -i64::from(value.scale()),
+            },
+        }
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negative {
+            f.write_str("-")?;
+        }
+        let (digits, exponent) = match &self.kind {
+            Kind::Finite { digits, exponent } => (digits, *exponent),
+            Kind::Infinity => return f.write_str("Infinity"),
+            Kind::NaN(payload) => return write!(f, "NaN{}", payload),
+            Kind::SNaN(payload) => return write!(f, "sNaN{}", payload),
+        };
+
+        // Python's to-scientific-string: plain notation unless the
+        // exponent is positive or the value is very small.
+        let left = exponent + digits.len() as i64;
+        let dot = if exponent <= 0 && left > -6 { left } else { 1 };
+
+        if dot <= 0 {
+            write!(f, "0.{}{}", "0".repeat(dot.unsigned_abs() as usize), digits)?;
+        } else {
+            let (int, frac) = digits.split_at(dot as usize);
+            f.write_str(int)?;
+            if !frac.is_empty() {
+                write!(f, ".{}", frac)?;
+            }
+        }
+        if left != dot {
+            write!(f, "E{:+}", left - dot)?;
+        }
+        Ok(())
+    }
+}
+
+fn all_digits(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// A NaN's diagnostic digits, without leading zeros.
+fn diagnostic(payload: &str) -> Result<String, Error> {
+    if !all_digits(payload) {
+        return Err(Error::ExpectedDecimal);
+    }
+    Ok(payload.trim_start_matches('0').into())
+}
+
+fn finite(s: &str) -> Result<Kind, Error> {
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(at) => (
+            &s[..at],
+            s[at + 1..]
+                .parse::<i64>()
+                .map_err(|_| Error::ExpectedDecimal)?,
+        ),
+        None => (s, 0),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if int.is_empty() && frac.is_empty() || !all_digits(int) || !all_digits(frac) {
+        return Err(Error::ExpectedDecimal);
+    }
+
+    let digits: String = match [int, frac].concat().trim_start_matches('0') {
+        "" => "0".into(),
+        digits => digits.into(),
+    };
+    // Printing places the point at `exponent + digits.len()`.
+    let exponent = i64::try_from(frac.len())
+        .ok()
+        .and_then(|len| exponent.checked_sub(len))
+        .filter(|exponent| exponent.checked_add(digits.len() as i64).is_some())
+        .ok_or(Error::ExpectedDecimal)?;
+
+    Ok(Kind::Finite { digits, exponent })
+}
+
+impl FromStr for Decimal {
+    type Err = Error;
+
+    /// Parse anything Python's `decimal.Decimal` takes from a string of
+    /// ASCII characters.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        // Python ignores surrounding whitespace and any underscores.
+        let s: String = s.trim().chars().filter(|c| *c != '_').collect();
+        let (negative, unsigned) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, &s[..]),
+        };
+
+        let lower = unsigned.to_ascii_lowercase();
+        let kind = if lower == "inf" || lower == "infinity" {
+            Kind::Infinity
+        } else if let Some(payload) = lower.strip_prefix("snan") {
+            Kind::SNaN(diagnostic(payload)?)
+        } else if let Some(payload) = lower.strip_prefix("nan") {
+            Kind::NaN(diagnostic(payload)?)
+        } else {
+            finite(unsigned)?
+        };
+
+        Ok(Decimal { negative, kind })
+    }
+}
+
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(DecimalVisitor)
+    }
+}
+
+struct DecimalVisitor;
+
+impl Visitor<'_> for DecimalVisitor {
+    type Value = Decimal;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a decimal")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Decimal, E> {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Decimal, E> {
+        let v = std::str::from_utf8(v).map_err(|_| E::custom(Error::ExpectedDecimal))?;
+        self.visit_str(v)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{from_bytes, to_bytes, V1};
+
+    /// `str(decimal.Decimal(input))` in Python 3.11, which Twisted sends.
+    const GOLDEN: &[(&str, &str)] = &[
+        ("0", "0"),
+        ("-0", "-0"),
+        ("0.00", "0.00"),
+        ("1.00", "1.00"),
+        ("-1.01", "-1.01"),
+        ("123456789.0", "123456789.0"),
+        ("0.000001", "0.000001"),
+        ("0.0000001", "1E-7"),
+        ("1.0E-17", "1.0E-17"),
+        ("-2.5e-8", "-2.5E-8"),
+        ("0E-10", "0E-10"),
+        ("12.5E-1", "1.25"),
+        ("1E+3", "1E+3"),
+        ("1e3", "1E+3"),
+        ("0E+3", "0E+3"),
+        ("-0E+2", "-0E+2"),
+        ("1E-29", "1E-29"),
+        ("1.5e100", "1.5E+100"),
+        (
+            "79228162514264337593543950336000",
+            "79228162514264337593543950336000",
+        ),
+        (
+            "0.1234567890123456789012345678901234",
+            "0.1234567890123456789012345678901234",
+        ),
+        ("1_000", "1000"),
+        ("1_0.0_1", "10.01"),
+        ("007.50", "7.50"),
+        (".5", "0.5"),
+        ("5.", "5"),
+        ("+1", "1"),
+        (" 1.5 ", "1.5"),
+        ("Infinity", "Infinity"),
+        ("-Infinity", "-Infinity"),
+        ("inf", "Infinity"),
+        ("-INF", "-Infinity"),
+        ("NaN", "NaN"),
+        ("-nan", "-NaN"),
+        ("nan12", "NaN12"),
+        ("NaN012", "NaN12"),
+        ("nan0", "NaN"),
+        ("sNaN", "sNaN"),
+        ("-sNaN7", "-sNaN7"),
+    ];
+
+    #[test]
+    fn golden() {
+        for (input, output) in GOLDEN {
+            let value: Decimal = input.parse().unwrap();
+            assert_eq!(value.to_string(), *output, "{}", input);
+            assert_eq!(output.parse::<Decimal>().unwrap(), value, "{}", output);
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        // Python raises on all of these too.
+        for input in [
+            "", "-", ".", "_", "1.2.3", "1e", "1e+", "e5", "abc", "NaNx", "inf1", "sNaN-1",
+        ] {
+            assert!(input.parse::<Decimal>().is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn rust_decimal() {
+        let finite = |s: &str| s.parse::<Decimal>().unwrap().to_finite();
+
+        let value = rust_decimal::Decimal::new(-1250, 2);
+        assert_eq!(finite("-12.50"), Some(value));
+        assert_eq!(Decimal::from(value).to_string(), "-12.50");
+
+        assert_eq!(finite("1E+3"), Some(rust_decimal::Decimal::from(1000)));
+        assert_eq!(finite("1E-29"), None);
+        assert_eq!(finite("1E+40"), None);
+        assert_eq!(Decimal::NAN.to_finite(), None);
+        assert_eq!(Decimal::NEG_INFINITY.to_string(), "-Infinity");
+    }
+
+    #[test]
+    fn fields() {
+        #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+        struct Price {
+            amount: Decimal,
+            limit: Decimal,
+        }
+
+        let price = Price {
+            amount: "-12.50".parse().unwrap(),
+            limit: Decimal::INFINITY,
+        };
+        let bytes = to_bytes::<V1, _>(&price).unwrap();
+        assert_eq!(
+            bytes,
+            b"\x00\x06amount\x00\x06-12.50\x00\x05limit\x00\x08Infinity\x00\x00".as_ref()
+        );
+        assert_eq!(from_bytes::<V1, _, Price>(bytes).unwrap(), price);
+    }
+}
@@ -0,0 +1,128 @@
+//! Send `None` as a key with an empty value, and read empty values as
+//! `None`, for peers that still expect that, with
+//! `#[serde(default, with = "amp_serde::empty_none")]`.
+//!
+//! Otherwise, `None` fields are left out and any value present is `Some`.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{Error, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+pub fn serialize<T, S>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize,
+    S: Serializer,
+{
+    match value {
+        Some(value) => serializer.serialize_some(value),
+        None => serializer.serialize_unit(),
+    }
+}
+
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    deserializer
+        .deserialize_newtype_struct(crate::AMP_EMPTY_NONE_COOKIE, OptionVisitor(PhantomData))
+}
+
+struct OptionVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for OptionVisitor<T> {
+    type Value = Option<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("an optional value")
+    }
+
+    fn visit_none<E: Error>(self) -> Result<Option<T>, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: Error>(self) -> Result<Option<T>, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Option<T>, D::Error> {
+        T::deserialize(deserializer).map(Some)
+    }
+
+    // Other formats do not know the cookie.
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Option<T>, D::Error> {
+        deserializer.deserialize_option(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::{from_bytes, to_bytes, V1};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Omitted {
+        a: Option<String>,
+        b: Option<u32>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Empty {
+        #[serde(default, with = "crate::empty_none")]
+        a: Option<String>,
+        #[serde(default, with = "crate::empty_none")]
+        b: Option<u32>,
+    }
+
+    #[test]
+    fn omit_none() {
+        let value = Omitted {
+            a: Some(String::new()),
+            b: None,
+        };
+        let bytes = to_bytes::<V1, _>(&value).unwrap();
+        assert_eq!(bytes, b"\x00\x01a\x00\x00\x00\x00".as_ref());
+        assert_eq!(from_bytes::<V1, _, Omitted>(bytes).unwrap(), value);
+
+        let none: Omitted = from_bytes::<V1, _, _>(b"\x00\x00".as_ref()).unwrap();
+        assert_eq!(none, Omitted { a: None, b: None });
+    }
+
+    #[test]
+    fn omit_none_entries() {
+        let mut map = BTreeMap::new();
+        map.insert("j", Some(String::new()));
+        map.insert("k", None);
+        let bytes = to_bytes::<V1, _>(&map).unwrap();
+        assert_eq!(bytes, b"\x00\x01j\x00\x00\x00\x00".as_ref());
+
+        let decoded: BTreeMap<String, Option<String>> = from_bytes::<V1, _, _>(bytes).unwrap();
+        assert_eq!(decoded.get("j"), Some(&Some(String::new())));
+        assert_eq!(decoded.get("k"), None);
+    }
+
+    #[test]
+    fn empty_none() {
+        let value = Empty {
+            a: None,
+            b: Some(3),
+        };
+        let bytes = to_bytes::<V1, _>(&value).unwrap();
+        assert_eq!(
+            bytes,
+            b"\x00\x01a\x00\x00\x00\x01b\x00\x013\x00\x00".as_ref()
+        );
+        assert_eq!(from_bytes::<V1, _, Empty>(bytes).unwrap(), value);
+
+        let missing: Empty = from_bytes::<V1, _, _>(b"\x00\x00".as_ref()).unwrap();
+        assert_eq!(missing, Empty { a: None, b: None });
+    }
+}
@@ -0,0 +1,391 @@
+//! Convert boxes to and from JSON, to craft and inspect messages by hand.
+//!
+//! A `Schema` gives the types of keys. Keys it leaves out become strings,
+//! or objects and arrays where they look like nested boxes and
+//! `AmpList`s.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use bytes::Bytes;
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+
+use crate::{
+    from_bytes_with, to_bytes_with, AmpDecoder, AmpEncoder, AmpValue, Error, Hints, Result, Step,
+};
+
+#[derive(Clone, Debug)]
+enum Field {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    Bytes,
+    List(Schema),
+}
+
+/// The types of the keys in a box.
+///
+/// Boxes are encoded with these keys first, in the order they were
+/// added, so that the output matches a struct declaring them the same way.
+#[derive(Clone, Debug, Default)]
+pub struct Schema {
+    fields: Vec<(String, Field)>,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn string(self, key: &str) -> Self {
+        self.field(key, Field::String)
+    }
+
+    pub fn integer(self, key: &str) -> Self {
+        self.field(key, Field::Integer)
+    }
+
+    /// A float, which is one of the strings `nan`, `inf` or `-inf` in JSON
+    /// if it is not finite.
+    pub fn float(self, key: &str) -> Self {
+        self.field(key, Field::Float)
+    }
+
+    pub fn boolean(self, key: &str) -> Self {
+        self.field(key, Field::Boolean)
+    }
+
+    /// Raw bytes, as base64 in JSON.
+    pub fn bytes(self, key: &str) -> Self {
+        self.field(key, Field::Bytes)
+    }
+
+    /// An `AmpList` of boxes following `items`.
+    pub fn list(self, key: &str, items: Schema) -> Self {
+        self.field(key, Field::List(items))
+    }
+
+    fn field(mut self, key: &str, field: Field) -> Self {
+        self.fields.retain(|(name, _)| name != key);
+        self.fields.push((key.into(), field));
+        self
+    }
+
+    fn get(&self, key: &str) -> Option<&Field> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, field)| field)
+    }
+
+    fn hints(&self, prefix: &str, mut hints: Hints) -> Hints {
+        for (key, field) in &self.fields {
+            let path = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+
+            hints = match field {
+                Field::List(items) => items.hints(&path, hints.list(&path)),
+                _ => hints.bytes(&path),
+            };
+        }
+        hints
+    }
+}
+
+/// Decode a whole box into a JSON object.
+pub fn to_json<V: AmpDecoder>(version: V, input: Bytes, schema: &Schema) -> Result<Value> {
+    let value = AmpValue::decode(version, input, &schema.hints("", Hints::new()))?;
+    box_to_json(version, &value, Some(schema)).map(Value::Object)
+}
+
+/// Encode a JSON object as a box. `null` values are left out.
+pub fn from_json<V: AmpEncoder>(version: V, value: &Value, schema: &Schema) -> Result<Vec<u8>> {
+    let value = match value {
+        Value::Object(map) => json_to_box(version, map, Some(schema))?,
+        _ => return Err(Error::ExpectedMapKey),
+    };
+    to_bytes_with(version, &value)
+}
+
+fn box_to_json<V: AmpDecoder>(
+    version: V,
+    value: &AmpValue,
+    schema: Option<&Schema>,
+) -> Result<Map<String, Value>> {
+    let mut map = Map::new();
+
+    for (key, value) in value.as_box().ok_or(Error::ExpectedMapKey)? {
+        let raw = value.as_bytes().map(|bytes| &bytes[..]);
+        let json = std::str::from_utf8(key)
+            .map_err(|_| Error::ExpectedUtf8)
+            .and_then(|name| {
+                let field = schema.and_then(|schema| schema.get(name));
+                value_to_json(version, field, value)
+            })
+            .map_err(|e| e.within(Step::Key(key), raw))?;
+
+        map.insert(String::from_utf8_lossy(key).into_owned(), json);
+    }
+
+    Ok(map)
+}
+
+fn value_to_json<V: AmpDecoder>(
+    version: V,
+    field: Option<&Field>,
+    value: &AmpValue,
+) -> Result<Value> {
+    let items = match (field, value) {
+        (Some(Field::List(items)), AmpValue::List(list)) => Some((list, Some(items))),
+        (None, AmpValue::List(list)) => Some((list, None)),
+        _ => None,
+    };
+    if let Some((list, schema)) = items {
+        return list
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                box_to_json(version, item, schema)
+                    .map(Value::Object)
+                    .map_err(|e| e.within(Step::Index(index), None))
+            })
+            .collect::<Result<_>>()
+            .map(Value::Array);
+    }
+
+    let bytes = match value {
+        AmpValue::Bytes(bytes) => bytes.clone(),
+        AmpValue::Box(_) if field.is_none() => {
+            return box_to_json(version, value, None).map(Value::Object)
+        }
+        _ => return Err(Error::Unsupported),
+    };
+
+    Ok(match field {
+        None | Some(Field::String) => Value::String(from_bytes_with(version, bytes)?),
+        Some(Field::Integer) => match from_bytes_with::<_, _, i64>(version, bytes.clone()) {
+            Ok(n) => n.into(),
+            Err(_) => from_bytes_with::<_, _, u64>(version, bytes)?.into(),
+        },
+        Some(Field::Float) => {
+            let n: f64 = from_bytes_with(version, bytes)?;
+            match Number::from_f64(n) {
+                Some(n) => Value::Number(n),
+                None if n.is_nan() => "nan".into(),
+                None if n > 0.0 => "inf".into(),
+                None => "-inf".into(),
+            }
+        }
+        Some(Field::Boolean) => Value::Bool(from_bytes_with(version, bytes)?),
+        Some(Field::Bytes) => Value::String(BASE64.encode(bytes)),
+        Some(Field::List(_)) => return Err(Error::ExpectedSeqValue),
+    })
+}
+
+fn json_to_box<V: AmpEncoder>(
+    version: V,
+    map: &Map<String, Value>,
+    schema: Option<&Schema>,
+) -> Result<AmpValue> {
+    let known = schema
+        .iter()
+        .flat_map(|schema| &schema.fields)
+        .filter_map(|(key, _)| map.get_key_value(key));
+    let unknown = map
+        .iter()
+        .filter(|(key, _)| schema.and_then(|schema| schema.get(key)).is_none());
+
+    let mut fields = Vec::with_capacity(map.len());
+    for (key, json) in known.chain(unknown) {
+        if json.is_null() {
+            continue;
+        }
+
+        let field = schema.and_then(|schema| schema.get(key));
+        let value = json_to_value(version, field, json)
+            .map_err(|e| e.within(Step::Key(key.as_bytes()), None))?;
+        fields.push((Bytes::copy_from_slice(key.as_bytes()), value));
+    }
+
+    Ok(AmpValue::Box(fields))
+}
+
+fn json_to_value<V: AmpEncoder>(
+    version: V,
+    field: Option<&Field>,
+    json: &Value,
+) -> Result<AmpValue> {
+    match (field, json) {
+        (Some(Field::List(_)) | None, Value::Array(items)) => {
+            let schema = match field {
+                Some(Field::List(schema)) => Some(schema),
+                _ => None,
+            };
+
+            items
+                .iter()
+                .enumerate()
+                .map(|(index, item)| {
+                    match item {
+                        Value::Object(map) => json_to_box(version, map, schema),
+                        _ => Err(Error::ExpectedMapKey),
+                    }
+                    .map_err(|e| e.within(Step::Index(index), None))
+                })
+                .collect::<Result<_>>()
+                .map(AmpValue::List)
+        }
+        (None, Value::Object(map)) => json_to_box(version, map, None),
+        (None | Some(Field::String), Value::String(text)) => encode(version, text),
+        (None, Value::Number(n)) => Ok(AmpValue::from(n.to_string().as_str())),
+        (None | Some(Field::Boolean), Value::Bool(b)) => encode(version, b),
+        (Some(Field::Integer), Value::Number(n)) => match (n.as_i64(), n.as_u64()) {
+            (Some(n), _) => encode(version, n),
+            (_, Some(n)) => encode(version, n),
+            _ => Err(Error::ExpectedInteger),
+        },
+        (Some(Field::Float), Value::Number(n)) => {
+            encode(version, n.as_f64().ok_or(Error::ExpectedFloat)?)
+        }
+        (Some(Field::Float), Value::String(text)) => match text.as_str() {
+            "nan" => encode(version, f64::NAN),
+            "inf" => encode(version, f64::INFINITY),
+            "-inf" => encode(version, f64::NEG_INFINITY),
+            _ => Err(Error::ExpectedFloat),
+        },
+        (Some(Field::Bytes), Value::String(text)) => BASE64
+            .decode(text)
+            .map(|bytes| AmpValue::Bytes(bytes.into()))
+            .map_err(|e| Error::Custom(format!("Invalid base64: {}", e))),
+        (Some(Field::String), _) => Err(Error::ExpectedUtf8),
+        (Some(Field::Integer), _) => Err(Error::ExpectedInteger),
+        (Some(Field::Float), _) => Err(Error::ExpectedFloat),
+        (Some(Field::Boolean), _) => Err(Error::ExpectedBool),
+        (Some(Field::Bytes), _) => Err(Error::Custom("Expected base64".into())),
+        (Some(Field::List(_)), _) => Err(Error::ExpectedSeqValue),
+        (None, _) => Err(Error::Unsupported),
+    }
+}
+
+fn encode<V: AmpEncoder, T: Serialize>(version: V, value: T) -> Result<AmpValue> {
+    Ok(AmpValue::Bytes(to_bytes_with(version, value)?.into()))
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Serialize;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{to_bytes, AmpList, V1, V2};
+
+    #[derive(Serialize)]
+    struct Op {
+        b: u64,
+        ratio: f64,
+    }
+
+    #[derive(Serialize)]
+    struct Batch {
+        name: String,
+        count: i64,
+        ok: bool,
+        #[serde(with = "crate::zero_copy")]
+        blob: Bytes,
+        ops: AmpList<Op>,
+        note: Option<String>,
+    }
+
+    fn schema() -> Schema {
+        Schema::new()
+            .string("name")
+            .integer("count")
+            .boolean("ok")
+            .bytes("blob")
+            .list("ops", Schema::new().integer("b").float("ratio"))
+    }
+
+    fn batch() -> Batch {
+        Batch {
+            name: "first".into(),
+            count: -3,
+            ok: true,
+            blob: Bytes::from_static(b"\x00\xff"),
+            ops: AmpList(vec![
+                Op {
+                    b: u64::MAX,
+                    ratio: 0.5,
+                },
+                Op {
+                    b: 2,
+                    ratio: f64::INFINITY,
+                },
+            ]),
+            note: None,
+        }
+    }
+
+    fn batch_json() -> Value {
+        json!({
+            "name": "first",
+            "count": -3,
+            "ok": true,
+            "blob": "AP8=",
+            "ops": [
+                {"b": u64::MAX, "ratio": 0.5},
+                {"b": 2, "ratio": "inf"},
+            ],
+        })
+    }
+
+    #[test]
+    fn same_as_structs() {
+        let bytes = to_bytes::<V1, _>(batch()).unwrap();
+
+        let mut json = batch_json();
+        json["note"] = Value::Null;
+        assert_eq!(from_json(V1, &json, &schema()).unwrap(), bytes);
+        assert_eq!(to_json(V1, bytes.into(), &schema()).unwrap(), batch_json());
+    }
+
+    #[test]
+    fn without_schema() {
+        let json = json!({
+            "name": "first",
+            "count": 7,
+            "ops": [{"b": "1"}, {"b": "2"}],
+            "nested": {"a": "x"},
+        });
+
+        let bytes = from_json(V2, &json, &Schema::new()).unwrap();
+        assert_eq!(
+            to_json(V2, bytes.into(), &Schema::new()).unwrap()["count"],
+            "7"
+        );
+
+        let json = json!({"ops": [{"b": "1"}, {"b": "2"}], "nested": {"a": "x"}});
+        let bytes = from_json(V2, &json, &Schema::new()).unwrap();
+        assert_eq!(to_json(V2, bytes.into(), &Schema::new()).unwrap(), json);
+    }
+
+    #[test]
+    fn errors() {
+        let mut json = batch_json();
+        json["ops"][1]["b"] = "two".into();
+
+        let error = from_json(V1, &json, &schema()).unwrap_err();
+        assert!(matches!(error.kind(), Error::ExpectedInteger));
+        assert_eq!(error.path(), Some("ops[1].b"));
+
+        let bytes = b"\x00\x05count\x00\x03abc\x00\x00";
+        let error = to_json(V1, Bytes::from_static(bytes), &schema()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            r#"Expected an integer at count (value "abc")"#
+        );
+    }
+}
@@ -1,15 +1,54 @@
+#[cfg(feature = "bigint")]
+pub mod bigint;
 mod de;
+#[cfg(feature = "decimal")]
+pub mod decimal;
+pub mod empty_none;
+#[cfg(feature = "json")]
+pub mod json;
 mod ser;
+pub mod tagged;
 mod types;
+mod value;
+pub mod zero_copy;
 
-pub use de::{from_bytes, AmpDecoder};
+pub use de::{from_bytes, from_bytes_with, from_slice, from_slice_with, AmpDecoder, Deserializer};
 pub use ser::*;
 pub use types::*;
+pub use value::{AmpValue, Hints};
 
 pub(crate) const AMP_LIST_COOKIE: &str = "AmpList-450784";
+pub(crate) const AMP_BYTES_COOKIE: &str = "AmpBytes-450784";
+pub(crate) const AMP_EMPTY_NONE_COOKIE: &str = "AmpEmptyNone-450784";
+pub(crate) const AMP_TAGGED_COOKIE: &str = "AmpTagged-450784";
+pub(crate) const AMP_VALUE_COOKIE: &str = "AmpValue-450784";
+pub(crate) const AMP_VARIANT_KEY: &str = "_variant";
+pub(crate) const AMP_VALUE_KEY: &str = "_value";
 pub(crate) const AMP_KEY_LIMIT: usize = 0xff;
 pub(crate) const AMP_VALUE_LIMIT: usize = 0xffff;
 pub(crate) const AMP_LENGTH_SIZE: usize = std::mem::size_of::<u16>();
 
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
 pub struct V1;
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
 pub struct V2;
+
+/// A protocol version picked at runtime rather than through `V1` or
+/// `V2`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Version {
+    V1,
+    V2,
+}
+
+impl From<V1> for Version {
+    fn from(_: V1) -> Version {
+        Version::V1
+    }
+}
+
+impl From<V2> for Version {
+    fn from(_: V2) -> Version {
+        Version::V2
+    }
+}
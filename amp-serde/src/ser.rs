@@ -1,44 +1,139 @@
 use std::convert::TryFrom;
 use std::io::{self, Write};
-use std::marker::PhantomData;
 
 const INITIAL_CAPACITY: usize = 256;
 
-use bytes::BufMut;
+use bytes::{BufMut, BytesMut};
 use serde::ser::{
-    Impossible, SerializeMap, SerializeSeq, SerializeStruct, SerializeTuple, SerializeTupleStruct,
-    SerializeTupleVariant,
+    SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
+    SerializeTupleStruct, SerializeTupleVariant,
 };
 use serde::Serialize;
 
-use crate::{Error, Result, AMP_KEY_LIMIT, AMP_LENGTH_SIZE, AMP_VALUE_LIMIT, V1, V2};
+use crate::{
+    Error, Result, Step, Version, AMP_KEY_LIMIT, AMP_LENGTH_SIZE, AMP_TAGGED_COOKIE, AMP_VALUE_KEY,
+    AMP_VALUE_LIMIT, AMP_VARIANT_KEY, V1, V2,
+};
+
+/// Output buffers the serializer can append to and patch lengths in.
+pub trait Buffer: BufMut + AsMut<[u8]> {
+    fn len(&self) -> usize;
+
+    fn truncate(&mut self, len: usize);
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Buffer for Vec<u8> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn truncate(&mut self, len: usize) {
+        Vec::truncate(self, len)
+    }
+}
+
+impl Buffer for BytesMut {
+    fn len(&self) -> usize {
+        BytesMut::len(self)
+    }
+
+    fn truncate(&mut self, len: usize) {
+        BytesMut::truncate(self, len)
+    }
+}
+
+impl<B: Buffer + ?Sized> Buffer for &mut B {
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+
+    fn truncate(&mut self, len: usize) {
+        (**self).truncate(len)
+    }
+}
 
 #[derive(Debug)]
-pub struct Serializer<V>(Vec<u8>, PhantomData<V>);
+pub struct Serializer<V, B = Vec<u8>>(B, V, Flags);
+
+#[derive(Debug, Default)]
+struct Flags {
+    /// Set while serializing a value marked with `amp_serde::tagged`.
+    tagged: bool,
+    /// Set once a `None` has been serialized.
+    none: bool,
+}
 
-impl<V> Default for Serializer<V> {
+impl<V: Default> Default for Serializer<V> {
     fn default() -> Serializer<V> {
-        Serializer(Vec::with_capacity(INITIAL_CAPACITY), PhantomData)
+        Serializer::with_version(Vec::with_capacity(INITIAL_CAPACITY), V::default())
+    }
+}
+
+impl<V: Default, B: Buffer> Serializer<V, B> {
+    /// Serialize by appending to `buf`.
+    pub fn new(buf: B) -> Self {
+        Serializer::with_version(buf, V::default())
+    }
+}
+
+impl<V, B: Buffer> Serializer<V, B> {
+    pub fn with_version(buf: B, version: V) -> Self {
+        Serializer(buf, version, Flags::default())
+    }
+
+    pub fn into_inner(self) -> B {
+        self.0
+    }
+
+    pub fn get_ref(&self) -> &B {
+        &self.0
+    }
+
+    /// Empty the buffer, keeping its capacity, to serialize again.
+    pub fn reset(&mut self) {
+        self.0.truncate(0);
+        self.2 = Flags::default();
     }
 }
 
 #[doc(hidden)]
-pub struct Compound<'a, V> {
-    ser: &'a mut Serializer<V>,
+pub struct Compound<'a, V, B> {
+    ser: &'a mut Serializer<V, B>,
+    index: usize,
+    /// Where the last map key started.
+    key: usize,
 }
 
-impl<'a, V> Compound<'a, V> {
-    fn new(ser: &'a mut Serializer<V>) -> Compound<'a, V> {
-        Compound { ser }
+impl<'a, V, B> Compound<'a, V, B> {
+    fn new(ser: &'a mut Serializer<V, B>) -> Compound<'a, V, B> {
+        // `amp_serde::tagged` marks the enum itself, not enums nested in
+        // other values.
+        ser.2.tagged = false;
+        Compound {
+            ser,
+            index: 0,
+            key: 0,
+        }
+    }
+
+    /// Serialize the next item of a list, noting its index on errors.
+    fn item(&mut self, f: impl FnOnce(&mut Serializer<V, B>) -> Result<()>) -> Result<()> {
+        let step = Step::Index(self.index);
+        self.index += 1;
+        f(self.ser).map_err(|e| e.within(step, None))
     }
 }
 
-impl<'a, V: AmpEncoder> SerializeSeq for Compound<'a, V> {
+impl<V: AmpEncoder, B: Buffer> SerializeSeq for Compound<'_, V, B> {
     type Ok = ();
     type Error = Error;
 
     fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
-        self.ser.push_value(value)
+        self.item(|ser| ser.push_value(value))
     }
 
     fn end(self) -> Result<()> {
@@ -46,12 +141,12 @@ impl<'a, V: AmpEncoder> SerializeSeq for Compound<'a, V> {
     }
 }
 
-impl<'a, V: AmpEncoder> SerializeTuple for Compound<'a, V> {
+impl<V: AmpEncoder, B: Buffer> SerializeTuple for Compound<'_, V, B> {
     type Ok = ();
     type Error = Error;
 
     fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
-        self.ser.push_value(value)
+        self.item(|ser| ser.push_value(value))
     }
 
     fn end(self) -> Result<()> {
@@ -59,12 +154,12 @@ impl<'a, V: AmpEncoder> SerializeTuple for Compound<'a, V> {
     }
 }
 
-impl<'a, V: AmpEncoder> SerializeTupleStruct for Compound<'a, V> {
+impl<V: AmpEncoder, B: Buffer> SerializeTupleStruct for Compound<'_, V, B> {
     type Ok = ();
     type Error = Error;
 
     fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
-        self.ser.push_value(value)
+        self.item(|ser| ser.push_value(value))
     }
 
     fn end(self) -> Result<()> {
@@ -72,13 +167,13 @@ impl<'a, V: AmpEncoder> SerializeTupleStruct for Compound<'a, V> {
     }
 }
 
-impl<'a, V: AmpEncoder> SerializeTupleVariant for Compound<'a, V> {
+impl<V: AmpEncoder, B: Buffer> SerializeTupleVariant for Compound<'_, V, B> {
     type Ok = ();
     type Error = Error;
 
     fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
         // Encode with no separator
-        value.serialize(&mut *self.ser)
+        self.item(|ser| value.serialize(ser))
     }
 
     fn end(self) -> Result<()> {
@@ -86,16 +181,17 @@ impl<'a, V: AmpEncoder> SerializeTupleVariant for Compound<'a, V> {
     }
 }
 
-impl<'a, V: AmpEncoder> SerializeMap for Compound<'a, V> {
+impl<V: AmpEncoder, B: Buffer> SerializeMap for Compound<'_, V, B> {
     type Ok = ();
     type Error = Error;
 
     fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<()> {
+        self.key = self.ser.0.len();
         self.ser.push_key(key)
     }
 
     fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
-        V::push_long_value(&mut self.ser, value)
+        self.ser.push_entry_value(self.key, value)
     }
 
     fn end(self) -> Result<Self::Ok> {
@@ -104,7 +200,24 @@ impl<'a, V: AmpEncoder> SerializeMap for Compound<'a, V> {
     }
 }
 
-impl<'a, V: AmpEncoder> SerializeStruct for Compound<'a, V> {
+impl<V: AmpEncoder, B: Buffer> SerializeStructVariant for Compound<'_, V, B> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        SerializeStruct::end(self)
+    }
+}
+
+impl<V: AmpEncoder, B: Buffer> SerializeStruct for Compound<'_, V, B> {
     type Ok = ();
     type Error = Error;
 
@@ -113,9 +226,11 @@ impl<'a, V: AmpEncoder> SerializeStruct for Compound<'a, V> {
         key: &'static str,
         value: &T,
     ) -> Result<()> {
+        let start = self.ser.0.len();
         self.ser.push_key(key)?;
-        V::push_long_value(&mut self.ser, value)?;
-        Ok(())
+        self.ser
+            .push_entry_value(start, value)
+            .map_err(|e| e.within(Step::Key(key.as_bytes()), None))
     }
 
     fn end(self) -> Result<Self::Ok> {
@@ -124,42 +239,72 @@ impl<'a, V: AmpEncoder> SerializeStruct for Compound<'a, V> {
     }
 }
 
-pub trait AmpEncoder: Sized {
-    fn push_long_value<T: Serialize + ?Sized>(ser: &mut Serializer<Self>, input: &T) -> Result<()>;
+pub trait AmpEncoder: Copy {
+    fn push_long_value<B: Buffer, T: Serialize + ?Sized>(
+        ser: &mut Serializer<Self, B>,
+        input: &T,
+    ) -> Result<()>;
 }
 
 impl AmpEncoder for V1 {
-    fn push_long_value<T: Serialize + ?Sized>(ser: &mut Serializer<Self>, input: &T) -> Result<()> {
+    fn push_long_value<B: Buffer, T: Serialize + ?Sized>(
+        ser: &mut Serializer<Self, B>,
+        input: &T,
+    ) -> Result<()> {
         ser.push_value(input)
     }
 }
 
 impl AmpEncoder for V2 {
-    fn push_long_value<T: Serialize + ?Sized>(ser: &mut Serializer<Self>, input: &T) -> Result<()> {
-        // Allocate a temporary buffer. Somewhat less efficient than
-        // recursive position tracking, but easy to get right for now.
-        let mut subser = Serializer::<V2>::default();
-        input.serialize(&mut subser)?;
-
-        let value = Vec::from(subser);
-        if value.is_empty() {
-            ser.push_bytes(b"\x00\x00");
-            return Ok(());
-        }
+    fn push_long_value<B: Buffer, T: Serialize + ?Sized>(
+        ser: &mut Serializer<Self, B>,
+        input: &T,
+    ) -> Result<()> {
+        ser.push_chunked_value(input)
+    }
+}
 
-        for chunk in value.chunks(AMP_VALUE_LIMIT) {
-            let length = u16::try_from(chunk.len()).unwrap();
-            ser.push_bytes(length.to_be_bytes().as_ref());
-            ser.push_bytes(chunk);
+impl AmpEncoder for Version {
+    fn push_long_value<B: Buffer, T: Serialize + ?Sized>(
+        ser: &mut Serializer<Self, B>,
+        input: &T,
+    ) -> Result<()> {
+        match ser.1 {
+            Version::V1 => ser.push_value(input),
+            Version::V2 => ser.push_chunked_value(input),
         }
-
-        Ok(())
     }
 }
 
-impl<V: AmpEncoder> Serializer<V> {
+impl<V: AmpEncoder, B: Buffer> Serializer<V, B> {
     fn push_bytes(&mut self, bytes: &[u8]) {
-        self.0.extend_from_slice(bytes)
+        self.0.put_slice(bytes)
+    }
+
+    /// Serialize a V2 value in place, then split it into segments by
+    /// moving each chunk up to make room for its length.
+    ///
+    /// A full segment means the value continues, so one whose length is
+    /// a multiple of the segment size ends with an empty segment.
+    fn push_chunked_value<T: Serialize + ?Sized>(&mut self, input: &T) -> Result<()> {
+        let start = self.prep_len();
+        input.serialize(&mut *self)?;
+
+        let length = self.0.len() - start - AMP_LENGTH_SIZE;
+        let extra = length / AMP_VALUE_LIMIT;
+        self.0.put_bytes(0, extra * AMP_LENGTH_SIZE);
+
+        let buf = self.0.as_mut();
+        for chunk in (0..=extra).rev() {
+            let from = start + AMP_LENGTH_SIZE + chunk * AMP_VALUE_LIMIT;
+            let len = (length - chunk * AMP_VALUE_LIMIT).min(AMP_VALUE_LIMIT);
+            let header = start + chunk * (AMP_LENGTH_SIZE + AMP_VALUE_LIMIT);
+            buf.copy_within(from..from + len, header + AMP_LENGTH_SIZE);
+            buf[header..header + AMP_LENGTH_SIZE]
+                .copy_from_slice(&u16::try_from(len).unwrap().to_be_bytes());
+        }
+
+        Ok(())
     }
 
     fn push_value<T: Serialize + ?Sized>(&mut self, input: &T) -> Result<()> {
@@ -191,7 +336,8 @@ impl<V: AmpEncoder> Serializer<V> {
             return Err(Error::ValueTooLong);
         }
         let length = u16::try_from(length).unwrap().to_be_bytes();
-        self.0[length_offset..length_offset + AMP_LENGTH_SIZE].copy_from_slice(length.as_ref());
+        self.0.as_mut()[length_offset..length_offset + AMP_LENGTH_SIZE]
+            .copy_from_slice(length.as_ref());
 
         Ok(())
     }
@@ -201,6 +347,31 @@ impl<V: AmpEncoder> Serializer<V> {
         input.serialize(&mut *self)?;
         self.write_len(length_offset, true)
     }
+
+    /// Push the value for the key written at `start`, leaving out the
+    /// whole entry if the value is `None`, as Twisted does for optional
+    /// arguments.
+    fn push_entry_value<T: Serialize + ?Sized>(&mut self, start: usize, value: &T) -> Result<()> {
+        let value_start = self.0.len();
+        self.2.none = false;
+        V::push_long_value(self, value)?;
+
+        let empty = self.0.len() == value_start + AMP_LENGTH_SIZE;
+        if empty && std::mem::take(&mut self.2.none) {
+            self.0.truncate(start);
+        }
+        Ok(())
+    }
+
+    /// Start a nested box naming the variant, if the enum is tagged.
+    fn push_variant(&mut self, variant: &'static str) -> Result<()> {
+        if !std::mem::take(&mut self.2.tagged) {
+            return Err(Error::Unsupported);
+        }
+
+        self.push_key(AMP_VARIANT_KEY)?;
+        V::push_long_value(self, variant)
+    }
 }
 
 impl<V> From<Serializer<V>> for Vec<u8> {
@@ -209,17 +380,17 @@ impl<V> From<Serializer<V>> for Vec<u8> {
     }
 }
 
-impl<'a, V: AmpEncoder> serde::Serializer for &'a mut Serializer<V> {
+impl<'a, V: AmpEncoder, B: Buffer> serde::Serializer for &'a mut Serializer<V, B> {
     type Ok = ();
     type Error = Error;
 
-    type SerializeSeq = Compound<'a, V>;
-    type SerializeTuple = Compound<'a, V>;
-    type SerializeTupleStruct = Compound<'a, V>;
-    type SerializeTupleVariant = Compound<'a, V>;
-    type SerializeMap = Compound<'a, V>;
-    type SerializeStruct = Compound<'a, V>;
-    type SerializeStructVariant = Impossible<Self::Ok, Self::Error>;
+    type SerializeSeq = Compound<'a, V, B>;
+    type SerializeTuple = Compound<'a, V, B>;
+    type SerializeTupleStruct = Compound<'a, V, B>;
+    type SerializeTupleVariant = Compound<'a, V, B>;
+    type SerializeMap = Compound<'a, V, B>;
+    type SerializeStruct = Compound<'a, V, B>;
+    type SerializeStructVariant = Compound<'a, V, B>;
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
         if v {
@@ -247,6 +418,11 @@ impl<'a, V: AmpEncoder> serde::Serializer for &'a mut Serializer<V> {
         Ok(())
     }
 
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok> {
+        write!(self, "{}", v)?;
+        Ok(())
+    }
+
     fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
         self.serialize_u64(v as u64)
     }
@@ -264,6 +440,11 @@ impl<'a, V: AmpEncoder> serde::Serializer for &'a mut Serializer<V> {
         Ok(())
     }
 
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok> {
+        write!(self, "{}", v)?;
+        Ok(())
+    }
+
     fn serialize_f32(self, v: f32) -> Result<Self::Ok> {
         self.serialize_f64(v.into())
     }
@@ -301,6 +482,7 @@ impl<'a, V: AmpEncoder> serde::Serializer for &'a mut Serializer<V> {
     }
 
     fn serialize_none(self) -> Result<Self::Ok> {
+        self.2.none = true;
         Ok(())
     }
 
@@ -320,27 +502,39 @@ impl<'a, V: AmpEncoder> serde::Serializer for &'a mut Serializer<V> {
         self,
         _name: &str,
         _idx: u32,
-        _variant: &'static str,
+        variant: &'static str,
     ) -> Result<Self::Ok> {
-        Err(Error::Unsupported)
+        self.serialize_str(variant)
     }
 
     fn serialize_newtype_struct<T: ?Sized + serde::Serialize>(
         self,
-        _name: &'static str,
+        name: &'static str,
         value: &T,
     ) -> Result<Self::Ok> {
-        value.serialize(self)
+        // Marks an enum whose data-carrying variants may be sent.
+        if name == AMP_TAGGED_COOKIE {
+            self.2.tagged = true;
+            let res = value.serialize(&mut *self);
+            self.2.tagged = false;
+            res
+        } else {
+            value.serialize(self)
+        }
     }
 
     fn serialize_newtype_variant<T: ?Sized + serde::Serialize>(
         self,
         _name: &'static str,
         _idx: u32,
-        _variant: &'static str,
-        _value: &T,
+        variant: &'static str,
+        value: &T,
     ) -> Result<Self::Ok> {
-        Err(Error::Unsupported)
+        self.push_variant(variant)?;
+        self.push_key(AMP_VALUE_KEY)?;
+        V::push_long_value(self, value)?;
+        self.0.put_u16(0);
+        Ok(())
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
@@ -386,10 +580,11 @@ impl<'a, V: AmpEncoder> serde::Serializer for &'a mut Serializer<V> {
         self,
         _name: &'static str,
         _id: u32,
-        _variant: &'static str,
+        variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        Err(Error::Unsupported)
+        self.push_variant(variant)?;
+        Ok(Compound::new(self))
     }
 
     fn is_human_readable(&self) -> bool {
@@ -398,9 +593,10 @@ impl<'a, V: AmpEncoder> serde::Serializer for &'a mut Serializer<V> {
     }
 }
 
-impl<V> Write for Serializer<V>
+impl<V, B> Write for Serializer<V, B>
 where
     V: AmpEncoder,
+    B: Buffer,
 {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.push_bytes(buf);
@@ -412,8 +608,168 @@ where
     }
 }
 
-pub fn to_bytes<V: AmpEncoder, T: Serialize>(value: T) -> Result<Vec<u8>> {
-    let mut serializer = Serializer::<V>::default();
+pub fn to_bytes<V: AmpEncoder + Default, T: Serialize>(value: T) -> Result<Vec<u8>> {
+    to_bytes_with(V::default(), value)
+}
+
+pub fn to_bytes_with<V: AmpEncoder, T: Serialize>(version: V, value: T) -> Result<Vec<u8>> {
+    let mut serializer = Serializer::with_version(Vec::with_capacity(INITIAL_CAPACITY), version);
     value.serialize(&mut serializer)?;
     Ok(serializer.into())
 }
+
+/// Append to `buf`, such as a pooled buffer or a socket write buffer.
+///
+/// On error, `buf` is left as it was.
+pub fn to_buf<V, B, T>(buf: &mut B, value: T) -> Result<()>
+where
+    V: AmpEncoder + Default,
+    B: Buffer + ?Sized,
+    T: Serialize,
+{
+    to_buf_with(V::default(), buf, value)
+}
+
+pub fn to_buf_with<V, B, T>(version: V, buf: &mut B, value: T) -> Result<()>
+where
+    V: AmpEncoder,
+    B: Buffer + ?Sized,
+    T: Serialize,
+{
+    let start = buf.len();
+    let res = value.serialize(&mut Serializer::with_version(&mut *buf, version));
+
+    if res.is_err() {
+        buf.truncate(start);
+    }
+    res
+}
+
+/// Write to `writer`. Lengths come before values, so the box is built in
+/// memory first.
+pub fn to_writer<V: AmpEncoder + Default, W: Write, T: Serialize>(
+    writer: W,
+    value: T,
+) -> Result<()> {
+    to_writer_with(V::default(), writer, value)
+}
+
+pub fn to_writer_with<V: AmpEncoder, W: Write, T: Serialize>(
+    version: V,
+    mut writer: W,
+    value: T,
+) -> Result<()> {
+    writer.write_all(&to_bytes_with(version, value)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::BytesMut;
+    use serde::Serialize;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct AB {
+        a: u32,
+        b: u64,
+    }
+
+    const AB_ENC: &[u8] = b"\x00\x01a\x00\x011\x00\x01b\x00\x012\x00\x00";
+
+    #[test]
+    fn buffers() {
+        let mut buf = BytesMut::from(&b"head"[..]);
+        to_buf::<V1, _, _>(&mut buf, AB { a: 1, b: 2 }).unwrap();
+        assert_eq!(buf, [b"head", AB_ENC].concat());
+
+        let mut long_key = std::collections::BTreeMap::new();
+        long_key.insert("k".repeat(300), "v");
+        assert!(matches!(
+            to_buf::<V1, _, _>(&mut buf, &long_key),
+            Err(Error::KeyTooLong)
+        ));
+        assert_eq!(buf, [b"head", AB_ENC].concat());
+
+        let mut out = Vec::new();
+        to_writer::<V1, _, _>(&mut out, AB { a: 1, b: 2 }).unwrap();
+        assert_eq!(out, AB_ENC);
+    }
+
+    #[test]
+    fn chunk_boundaries() {
+        for length in [0, 1, 65_534, 65_535, 65_536, 131_070, 200_000] {
+            let mut value = std::collections::BTreeMap::new();
+            value.insert("v", "x".repeat(length));
+            let bytes = to_bytes::<V2, _>(&value).unwrap();
+
+            let segments = length / AMP_VALUE_LIMIT + 1;
+            assert_eq!(bytes.len(), 3 + segments * AMP_LENGTH_SIZE + length + 2);
+            let last = 3 + (segments - 1) * (AMP_LENGTH_SIZE + AMP_VALUE_LIMIT);
+            let last_len = u16::from_be_bytes([bytes[last], bytes[last + 1]]);
+            assert_eq!(usize::from(last_len), length % AMP_VALUE_LIMIT);
+
+            let decoded: std::collections::BTreeMap<String, String> =
+                crate::from_bytes::<V2, _, _>(bytes).unwrap();
+            assert_eq!(decoded["v"], value["v"]);
+        }
+    }
+
+    #[test]
+    fn reset() {
+        let mut serializer = Serializer::<V2>::default();
+
+        for _ in 0..2 {
+            AB { a: 1, b: 2 }.serialize(&mut serializer).unwrap();
+            assert_eq!(serializer.get_ref(), AB_ENC);
+            serializer.reset();
+        }
+        assert!(serializer.get_ref().capacity() >= AB_ENC.len());
+    }
+
+    #[test]
+    fn item_paths() {
+        #[derive(Serialize)]
+        enum Shape {
+            Empty,
+            Circle(u32),
+        }
+
+        #[derive(Serialize)]
+        struct Shapes {
+            shapes: Vec<Shape>,
+        }
+
+        let value = Shapes {
+            shapes: vec![Shape::Empty, Shape::Circle(1)],
+        };
+        let error = to_bytes::<V1, _>(&value).unwrap_err();
+        assert!(matches!(error.kind(), Error::Unsupported));
+        assert_eq!(error.path(), Some("shapes[1]"));
+    }
+
+    #[test]
+    fn wide_integers() {
+        #[derive(Serialize, serde::Deserialize, Debug, PartialEq)]
+        struct Wide {
+            i: i128,
+            u: u128,
+        }
+
+        let value = Wide {
+            i: i128::MIN,
+            u: u128::MAX,
+        };
+        let bytes = to_bytes::<V1, _>(&value).unwrap();
+        assert_eq!(
+            bytes,
+            [
+                b"\x00\x01i\x00\x28-170141183460469231731687303715884105728".as_ref(),
+                b"\x00\x01u\x00\x27340282366920938463463374607431768211455\x00\x00",
+            ]
+            .concat()
+        );
+        assert_eq!(crate::from_bytes::<V1, _, Wide>(bytes).unwrap(), value);
+    }
+}
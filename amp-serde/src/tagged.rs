@@ -0,0 +1,163 @@
+//! Opt in to sending an enum's data-carrying variants, with
+//! `#[serde(with = "amp_serde::tagged")]`.
+//!
+//! Unit variants are always sent as their name. Other variants become a
+//! nested box naming the variant under `_variant`, alongside the fields of
+//! a struct variant or the `_value` of a newtype variant. Tuple variants
+//! are not supported.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize,
+    S: Serializer,
+{
+    serializer.serialize_newtype_struct(crate::AMP_TAGGED_COOKIE, value)
+}
+
+/// Both forms are always accepted, so this is only for symmetry.
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer)
+}
+
+#[cfg(test)]
+mod test {
+    use serde::{Deserialize, Serialize};
+
+    use crate::{from_bytes, to_bytes, AmpList, Error, V1, V2};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Status {
+        Active,
+        #[serde(rename = "disabled")]
+        Disabled,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Shape {
+        Empty,
+        Circle(u32),
+        Rect { w: u32, h: u32 },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Plain {
+        status: Status,
+        shape: Shape,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Tagged {
+        status: Status,
+        #[serde(with = "crate::tagged")]
+        shape: Shape,
+    }
+
+    #[test]
+    fn unit_variants() {
+        let value = Plain {
+            status: Status::Disabled,
+            shape: Shape::Empty,
+        };
+        let bytes = to_bytes::<V1, _>(&value).unwrap();
+        assert_eq!(
+            bytes,
+            b"\x00\x06status\x00\x08disabled\x00\x05shape\x00\x05Empty\x00\x00".as_ref()
+        );
+        assert_eq!(from_bytes::<V1, _, Plain>(bytes).unwrap(), value);
+
+        let value = Plain {
+            status: Status::Active,
+            shape: Shape::Circle(1),
+        };
+        let error = to_bytes::<V1, _>(&value).unwrap_err();
+        assert!(matches!(error.kind(), Error::Unsupported));
+        assert_eq!(error.path(), Some("shape"));
+    }
+
+    #[test]
+    fn tagged_variants() {
+        let value = Tagged {
+            status: Status::Active,
+            shape: Shape::Rect { w: 2, h: 3 },
+        };
+        let bytes = to_bytes::<V1, _>(&value).unwrap();
+        assert_eq!(
+            bytes,
+            [
+                b"\x00\x06status\x00\x06Active\x00\x05shape\x00\x1e".as_ref(),
+                b"\x00\x08_variant\x00\x04Rect\x00\x01w\x00\x012\x00\x01h\x00\x013\x00\x00",
+                b"\x00\x00",
+            ]
+            .concat()
+        );
+        assert_eq!(from_bytes::<V1, _, Tagged>(bytes).unwrap(), value);
+
+        for shape in [Shape::Empty, Shape::Circle(7)] {
+            let value = Tagged {
+                status: Status::Active,
+                shape,
+            };
+            let bytes = to_bytes::<V2, _>(&value).unwrap();
+            assert_eq!(from_bytes::<V2, _, Tagged>(bytes).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn only_the_marked_enum() {
+        #[derive(Serialize, Debug)]
+        struct Inner {
+            s: Shape,
+        }
+
+        #[derive(Serialize)]
+        struct Outer {
+            #[serde(with = "crate::tagged")]
+            inner: Inner,
+        }
+
+        #[derive(Serialize)]
+        struct Items {
+            #[serde(with = "crate::tagged")]
+            items: AmpList<Inner>,
+        }
+
+        let value = Outer {
+            inner: Inner {
+                s: Shape::Circle(3),
+            },
+        };
+        let error = to_bytes::<V1, _>(&value).unwrap_err();
+        assert!(matches!(error.kind(), Error::Unsupported));
+        assert_eq!(error.path(), Some("inner.s"));
+
+        let value = Items {
+            items: AmpList(vec![
+                Inner {
+                    s: Shape::Circle(3),
+                },
+                Inner {
+                    s: Shape::Circle(4),
+                },
+            ]),
+        };
+        let error = to_bytes::<V1, _>(&value).unwrap_err();
+        assert!(matches!(error.kind(), Error::Unsupported));
+        assert_eq!(error.path(), Some("items[0].s"));
+    }
+
+    #[test]
+    fn missing_variant() {
+        let bytes =
+            b"\x00\x06status\x00\x06Active\x00\x05shape\x00\x08\x00\x01w\x00\x012\x00\x00\x00\x00";
+
+        let error = from_bytes::<V1, _, Tagged>(bytes.as_ref()).unwrap_err();
+        assert!(matches!(error.kind(), Error::ExpectedVariant));
+        assert_eq!(error.path(), Some("shape"));
+    }
+}
@@ -74,19 +74,89 @@ pub enum Error {
     RemainingBytes,
     ExpectedInteger,
     ExpectedFloat,
+    ExpectedDecimal,
     ExpectedUtf8,
     ExpectedChar,
     ExpectedMapKey,
     ExpectedMapValue,
     ExpectedSeqLength,
     ExpectedSeqValue,
+    ExpectedVariant,
 
     Custom(String),
     Unsupported,
+
+    /// Another error, with the path of the value it happened in, e.g.
+    /// `ops[2].b`, and the start of that value.
+    At {
+        path: String,
+        value: Option<String>,
+        error: Box<Error>,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// How much of an offending value errors show.
+const PREVIEW_LEN: usize = 32;
+
+/// One step into a value, towards where an error happened.
+pub(crate) enum Step<'a> {
+    Key(&'a [u8]),
+    Index(usize),
+}
+
+impl Error {
+    /// The error without its path.
+    pub fn kind(&self) -> &Error {
+        match self {
+            Error::At { error, .. } => error,
+            error => error,
+        }
+    }
+
+    /// Where in the box the error happened, if inside a value.
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            Error::At { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Record that this happened within `step`, which held `value`.
+    pub(crate) fn within(self, step: Step<'_>, value: Option<&[u8]>) -> Error {
+        let step = match step {
+            Step::Key(key) => String::from_utf8_lossy(key).into_owned(),
+            Step::Index(index) => format!("[{}]", index),
+        };
+
+        match self {
+            Error::At { path, value, error } => {
+                let separator = if path.starts_with('[') { "" } else { "." };
+                Error::At {
+                    path: format!("{}{}{}", step, separator, path),
+                    value,
+                    error,
+                }
+            }
+            error => Error::At {
+                path: step,
+                value: value.map(preview),
+                error: Box::new(error),
+            },
+        }
+    }
+}
+
+fn preview(value: &[u8]) -> String {
+    let shown = value.len().min(PREVIEW_LEN);
+    let mut preview = value[..shown].escape_ascii().to_string();
+    if shown < value.len() {
+        preview.push_str("...");
+    }
+    preview
+}
+
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
         Self::IO(err)
@@ -95,7 +165,36 @@ impl From<std::io::Error> for Error {
 
 impl Display for Error {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-        write!(fmt, "{:?}", self)
+        match self {
+            Error::IO(e) => write!(fmt, "I/O error: {}", e),
+            Error::KeyTooLong => write!(fmt, "Key longer than 255 bytes"),
+            Error::EmptyKey => write!(fmt, "Empty key"),
+            Error::ValueTooLong => write!(fmt, "Value longer than 65535 bytes"),
+            Error::ExpectedBool => write!(fmt, "Expected a boolean"),
+            Error::RemainingBytes => write!(fmt, "Unexpected bytes after the value"),
+            Error::ExpectedInteger => write!(fmt, "Expected an integer"),
+            Error::ExpectedFloat => write!(fmt, "Expected a float"),
+            Error::ExpectedDecimal => write!(fmt, "Expected a decimal"),
+            Error::ExpectedUtf8 => write!(fmt, "Expected UTF-8 text"),
+            Error::ExpectedChar => write!(fmt, "Expected a single character"),
+            Error::ExpectedMapKey => write!(fmt, "Expected a key"),
+            Error::ExpectedMapValue => write!(fmt, "Expected a value"),
+            Error::ExpectedSeqLength => write!(fmt, "Expected the length of a list item"),
+            Error::ExpectedSeqValue => write!(fmt, "Expected a list item"),
+            Error::ExpectedVariant => write!(fmt, "Expected an enum variant"),
+            Error::Custom(msg) => write!(fmt, "{}", msg),
+            Error::Unsupported => write!(fmt, "Unsupported type"),
+            Error::At {
+                path,
+                value: Some(value),
+                error,
+            } => write!(fmt, "{} at {} (value \"{}\")", error, path, value),
+            Error::At {
+                path,
+                value: None,
+                error,
+            } => write!(fmt, "{} at {}", error, path),
+        }
     }
 }
 
@@ -196,7 +295,9 @@ where
 
 #[cfg(test)]
 mod test {
-    use crate::{from_bytes, to_bytes, AmpList, Error, V1};
+    use crate::{
+        from_bytes, from_bytes_with, to_bytes, to_bytes_with, AmpList, Error, Version, V1,
+    };
     use serde::{Deserialize, Serialize};
 
     const LIST_ENC: [u8; 42] = [
@@ -231,6 +332,23 @@ mod test {
         );
     }
 
+    #[test]
+    fn runtime_version() {
+        let list = AmpList(vec![
+            AB { a: 1, b: 2 },
+            AB { a: 3, b: 4 },
+            AB { a: 5, b: 6 },
+        ]);
+        let bytes = to_bytes_with(Version::V1, &list).unwrap();
+        assert_eq!(bytes, LIST_ENC.as_ref());
+
+        // V2 only differs once a value spans several segments.
+        let v2 = to_bytes_with(Version::V2, &list).unwrap();
+        assert_eq!(v2, LIST_ENC.as_ref());
+        let decoded: AmpList<AB> = from_bytes_with(Version::V2, v2).unwrap();
+        assert_eq!(decoded.0, list.0);
+    }
+
     #[test]
     fn trailling_dicts() {
         match from_bytes::<V1, _, std::collections::BTreeMap<Vec<u8>, Vec<u8>>>(LIST_ENC.to_vec()) {
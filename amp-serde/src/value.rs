@@ -0,0 +1,399 @@
+//! Boxes decoded without a schema, for tools that inspect traffic.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Index;
+
+use bytes::Bytes;
+use serde::ser::{Error as _, SerializeTupleVariant};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::de::{read_segment, read_value, SHARED_VERSION};
+use crate::zero_copy::BytesVisitor;
+use crate::{AmpDecoder, Error, Result, Step, Version, AMP_LIST_COOKIE, AMP_VALUE_COOKIE};
+
+/// Any AMP value.
+///
+/// Nested boxes and `AmpList`s look like any other bytes on the wire, so
+/// decoding them is a guess unless `Hints` say what a key holds.
+#[derive(Clone, PartialEq, Eq)]
+pub enum AmpValue {
+    Bytes(Bytes),
+    /// Entries in the order they were sent.
+    Box(Vec<(Bytes, AmpValue)>),
+    /// An `AmpList`, whose items are all `Box`es.
+    List(Vec<AmpValue>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    Bytes,
+    Box,
+    List,
+}
+
+/// What some keys hold, by their path through nested boxes such as
+/// `ops.b`. Paths skip over list items.
+#[derive(Clone, Debug, Default)]
+pub struct Hints {
+    kinds: BTreeMap<String, Kind>,
+}
+
+impl Hints {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Never decode `path`.
+    pub fn bytes(self, path: &str) -> Self {
+        self.kind(path, Kind::Bytes)
+    }
+
+    /// Decode `path` as a nested box.
+    pub fn nested(self, path: &str) -> Self {
+        self.kind(path, Kind::Box)
+    }
+
+    /// Decode `path` as an `AmpList`.
+    pub fn list(self, path: &str) -> Self {
+        self.kind(path, Kind::List)
+    }
+
+    fn kind(mut self, path: &str, kind: Kind) -> Self {
+        self.kinds.insert(path.into(), kind);
+        self
+    }
+}
+
+type RawEntries = Vec<(Bytes, Bytes)>;
+
+/// Split `input` into boxes, without looking inside their values.
+fn split_boxes<V: AmpDecoder>(version: V, mut input: Bytes) -> Result<Vec<RawEntries>> {
+    let mut boxes = Vec::new();
+
+    while !input.is_empty() {
+        let mut entries = Vec::new();
+        loop {
+            let key = read_segment(&mut input).map_err(|_| Error::ExpectedMapKey)?;
+            if key.is_empty() {
+                break;
+            } else if key.len() > crate::AMP_KEY_LIMIT {
+                return Err(Error::KeyTooLong);
+            }
+
+            let value = read_value(version, &mut input)?.into_bytes();
+            entries.push((key, value));
+        }
+        boxes.push(entries);
+    }
+
+    Ok(boxes)
+}
+
+/// Whether `entries` look like a box rather than a coincidence.
+fn plausible(entries: &RawEntries) -> bool {
+    !entries.is_empty() && entries.iter().all(|(key, _)| printable(key).is_some())
+}
+
+fn printable(bytes: &[u8]) -> Option<&str> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .filter(|text| !text.chars().any(char::is_control))
+}
+
+impl AmpValue {
+    /// Decode a whole box, guessing what is nested in it except where
+    /// `hints` say.
+    pub fn decode<V: AmpDecoder>(version: V, input: Bytes, hints: &Hints) -> Result<AmpValue> {
+        let mut boxes = split_boxes(version, input)?;
+        match boxes.len() {
+            1 => Self::from_entries(version, boxes.remove(0), "", hints),
+            0 => Err(Error::ExpectedMapKey),
+            _ => Err(Error::RemainingBytes),
+        }
+    }
+
+    fn from_entries<V: AmpDecoder>(
+        version: V,
+        entries: RawEntries,
+        path: &str,
+        hints: &Hints,
+    ) -> Result<AmpValue> {
+        let mut fields = Vec::with_capacity(entries.len());
+
+        for (key, value) in entries {
+            let name = String::from_utf8_lossy(&key);
+            let path = if path.is_empty() {
+                name.into_owned()
+            } else {
+                format!("{}.{}", path, name)
+            };
+
+            let decoded = Self::from_value(version, value.clone(), &path, hints)
+                .map_err(|e| e.within(Step::Key(&key), Some(&value)))?;
+            fields.push((key, decoded));
+        }
+
+        Ok(AmpValue::Box(fields))
+    }
+
+    fn from_value<V: AmpDecoder>(
+        version: V,
+        value: Bytes,
+        path: &str,
+        hints: &Hints,
+    ) -> Result<AmpValue> {
+        let boxes = match hints.kinds.get(path) {
+            Some(Kind::Bytes) => return Ok(AmpValue::Bytes(value)),
+            Some(_) => split_boxes(version, value)?,
+            None => match split_boxes(version, value.clone()) {
+                Ok(boxes) if !boxes.is_empty() && boxes.iter().all(plausible) => boxes,
+                _ => return Ok(AmpValue::Bytes(value)),
+            },
+        };
+
+        let mut items = boxes
+            .into_iter()
+            .enumerate()
+            .map(|(index, entries)| {
+                Self::from_entries(version, entries, path, hints)
+                    .map_err(|e| e.within(Step::Index(index), None))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        match hints.kinds.get(path) {
+            Some(Kind::List) => Ok(AmpValue::List(items)),
+            Some(_) if items.len() != 1 => Err(Error::RemainingBytes),
+            None if items.len() != 1 => Ok(AmpValue::List(items)),
+            _ => Ok(items.remove(0)),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&Bytes> {
+        match self {
+            AmpValue::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes()
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+    }
+
+    pub fn as_box(&self) -> Option<&[(Bytes, AmpValue)]> {
+        match self {
+            AmpValue::Box(fields) => Some(fields),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[AmpValue]> {
+        match self {
+            AmpValue::List(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// The value of `key`, if this is a box with it.
+    pub fn get(&self, key: &str) -> Option<&AmpValue> {
+        self.as_box()?
+            .iter()
+            .find(|(name, _)| name == key.as_bytes())
+            .map(|(_, value)| value)
+    }
+
+    /// The item at `index`, if this is a list that long.
+    pub fn item(&self, index: usize) -> Option<&AmpValue> {
+        self.as_list()?.get(index)
+    }
+}
+
+impl Index<&str> for AmpValue {
+    type Output = AmpValue;
+
+    fn index(&self, key: &str) -> &AmpValue {
+        self.get(key)
+            .unwrap_or_else(|| panic!("no key {:?} in {:?}", key, self))
+    }
+}
+
+impl Index<usize> for AmpValue {
+    type Output = AmpValue;
+
+    fn index(&self, index: usize) -> &AmpValue {
+        self.item(index)
+            .unwrap_or_else(|| panic!("no item {} in {:?}", index, self))
+    }
+}
+
+impl From<Bytes> for AmpValue {
+    fn from(bytes: Bytes) -> Self {
+        AmpValue::Bytes(bytes)
+    }
+}
+
+impl From<&str> for AmpValue {
+    fn from(value: &str) -> Self {
+        AmpValue::Bytes(Bytes::copy_from_slice(value.as_bytes()))
+    }
+}
+
+/// Shows bytes as a string where they are printable.
+struct Printable<'a>(&'a [u8]);
+
+impl fmt::Debug for Printable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match printable(self.0) {
+            Some(text) => write!(f, "{:?}", text),
+            None => write!(f, "b\"{}\"", self.0.escape_ascii()),
+        }
+    }
+}
+
+impl fmt::Debug for AmpValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AmpValue::Bytes(bytes) => Printable(bytes).fmt(f),
+            AmpValue::Box(fields) => f
+                .debug_map()
+                .entries(fields.iter().map(|(key, value)| (Printable(key), value)))
+                .finish(),
+            AmpValue::List(items) => f.debug_list().entries(items).finish(),
+        }
+    }
+}
+
+/// Serializes a box's entries as a map, keeping their order.
+struct Entries<'a>(&'a [(Bytes, AmpValue)]);
+
+impl Serialize for Entries<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(key, value)| (key, value)))
+    }
+}
+
+impl Serialize for AmpValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            AmpValue::Bytes(bytes) => serializer.serialize_bytes(bytes),
+            AmpValue::Box(fields) => Entries(fields).serialize(serializer),
+            AmpValue::List(items) => {
+                let mut s = serializer.serialize_tuple_variant(
+                    AMP_LIST_COOKIE,
+                    0,
+                    "shaken, not stirred",
+                    0,
+                )?;
+
+                for item in items {
+                    match item {
+                        AmpValue::Box(fields) => s.serialize_field(&Entries(fields))?,
+                        _ => return Err(S::Error::custom("AmpList items must be boxes")),
+                    }
+                }
+                s.end()
+            }
+        }
+    }
+}
+
+/// Guesses what is nested in the value, reading values as the AMP
+/// deserializer's version does. Other deserializers are taken to hand
+/// over V2.
+impl<'de> Deserialize<'de> for AmpValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let bytes = deserializer.deserialize_newtype_struct(AMP_VALUE_COOKIE, BytesVisitor);
+        let version = SHARED_VERSION.with(|shared| shared.take());
+        let bytes = bytes?;
+        Ok(AmpValue::from_value(
+            version.unwrap_or(Version::V2),
+            bytes.clone(),
+            "",
+            &Hints::default(),
+        )
+        .unwrap_or(AmpValue::Bytes(bytes)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{from_bytes, to_bytes, AmpList, V1};
+
+    #[derive(Serialize, Deserialize)]
+    struct Op {
+        b: u32,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Batch {
+        name: String,
+        ops: AmpList<Op>,
+        one: AmpList<Op>,
+    }
+
+    fn batch() -> Bytes {
+        let batch = Batch {
+            name: "first".into(),
+            ops: AmpList(vec![Op { b: 1 }, Op { b: 2 }]),
+            one: AmpList(vec![Op { b: 3 }]),
+        };
+        to_bytes::<V1, _>(batch).unwrap().into()
+    }
+
+    #[test]
+    fn guesses() {
+        let value = AmpValue::decode(V1, batch(), &Hints::new()).unwrap();
+
+        assert_eq!(value["name"].as_str(), Some("first"));
+        assert_eq!(value["ops"][1]["b"].as_str(), Some("2"));
+        // A single item list looks just like a box.
+        assert_eq!(value["one"]["b"].as_str(), Some("3"));
+        assert_eq!(value.get("missing"), None);
+
+        assert_eq!(to_bytes::<V1, _>(&value).unwrap(), batch());
+        assert_eq!(from_bytes::<V1, _, AmpValue>(batch()).unwrap(), value);
+    }
+
+    #[test]
+    fn hints() {
+        let hints = Hints::new().list("one").bytes("ops");
+        let value = AmpValue::decode(V1, batch(), &hints).unwrap();
+
+        assert_eq!(value["one"][0]["b"].as_str(), Some("3"));
+        assert!(value["ops"].as_bytes().is_some());
+        assert_eq!(to_bytes::<V1, _>(&value).unwrap(), batch());
+
+        let error = AmpValue::decode(V1, batch(), &Hints::new().nested("ops")).unwrap_err();
+        assert!(matches!(error.kind(), Error::RemainingBytes));
+        assert_eq!(error.path(), Some("ops"));
+    }
+
+    #[test]
+    fn reader_version() {
+        // A full-length value, which V2 would take to continue.
+        let mut blob = std::collections::BTreeMap::new();
+        blob.insert("blob", "x".repeat(0xffff));
+        let bytes = Bytes::from(to_bytes::<V1, _>(&blob).unwrap());
+
+        let value = from_bytes::<V1, _, AmpValue>(bytes).unwrap();
+        assert_eq!(value["blob"].as_bytes().map(Bytes::len), Some(0xffff));
+    }
+
+    #[test]
+    fn debug() {
+        let value = AmpValue::decode(V1, batch(), &Hints::new()).unwrap();
+        assert_eq!(
+            format!("{:?}", value),
+            r#"{"name": "first", "ops": [{"b": "1"}, {"b": "2"}], "one": {"b": "3"}}"#
+        );
+
+        assert_eq!(
+            format!("{:?}", AmpValue::from(Bytes::from_static(b"\x00\xffok"))),
+            r#"b"\x00\xffok""#
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! Deserialize `Bytes` fields as slices of the input `Bytes` rather than
+//! copies, with `#[serde(with = "amp_serde::zero_copy")]`.
+//!
+//! Other deserializers, and `from_slice`, still copy.
+
+use std::fmt;
+
+use bytes::Bytes;
+use serde::de::{Error, Visitor};
+use serde::{Deserializer, Serializer};
+
+use crate::de::SHARED_BYTES;
+
+pub fn serialize<S: Serializer>(value: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(value)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+    deserializer.deserialize_newtype_struct(crate::AMP_BYTES_COOKIE, BytesVisitor)
+}
+
+pub(crate) struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Bytes;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("bytes")
+    }
+
+    // The AMP deserializer left the value for us.
+    fn visit_unit<E: Error>(self) -> Result<Bytes, E> {
+        SHARED_BYTES
+            .with(|shared| shared.borrow_mut().take())
+            .ok_or_else(|| E::custom("no shared bytes"))
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Bytes, D::Error> {
+        deserializer.deserialize_byte_buf(self)
+    }
+
+    fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Bytes, E> {
+        Ok(Bytes::copy_from_slice(v))
+    }
+
+    fn visit_byte_buf<E: Error>(self, v: Vec<u8>) -> Result<Bytes, E> {
+        Ok(v.into())
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<Bytes, E> {
+        Ok(Bytes::copy_from_slice(v.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use serde::{Deserialize, Serialize};
+
+    use crate::{from_bytes, from_slice, to_bytes, V1};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Blob {
+        #[serde(with = "crate::zero_copy")]
+        data: Bytes,
+        plain: Bytes,
+    }
+
+    #[test]
+    fn shares_input() {
+        let blob = Blob {
+            data: Bytes::from_static(b"shared"),
+            plain: Bytes::from_static(b"copied"),
+        };
+        let input = Bytes::from(to_bytes::<V1, _>(&blob).unwrap());
+
+        let value: Blob = from_bytes::<V1, _, _>(input.clone()).unwrap();
+        assert_eq!(value, blob);
+        assert!(input.as_ptr_range().contains(&value.data.as_ptr()));
+        assert!(!input.as_ptr_range().contains(&value.plain.as_ptr()));
+
+        let value: Blob = from_slice::<V1, _>(&input).unwrap();
+        assert_eq!(value, blob);
+    }
+}